@@ -1,12 +1,15 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use structopt::StructOpt;
 
-use keeper_primitives::{config::Error as ConfigError, ChannelFiles};
+use keeper_primitives::{config::Error as ConfigError, ChannelFiles, Stage};
 
-const EVENT_TO_IPFS_CHANNEL: &str = "event2ipfs";
-const VERIFY_TO_ATTEST_CHANNEL: &str = "verify2attest";
-const ATTEST_TO_SUBMIT_CHANNEL: &str = "attest2submit";
+use crate::role::{self, Role};
+
+pub(crate) const EVENT_TO_IPFS_CHANNEL: &str = "event2ipfs";
+pub(crate) const VERIFY_TO_ATTEST_CHANNEL: &str = "verify2attest";
+pub(crate) const ATTEST_TO_SUBMIT_CHANNEL: &str = "attest2submit";
+const DATABASE_DIR: &str = "db";
 
 #[derive(Debug, StructOpt)]
 #[structopt(name = "zcloak Keeper", about = "zCloak keeper node start config")]
@@ -16,6 +19,44 @@ pub enum Opt {
 		#[structopt(flatten)]
 		options: StartOptions,
 	},
+	///inspect, requeue or drop messages the keeper could not parse
+	DeadLetter {
+		///The zCloak keeper node msg queue cache directory
+		#[structopt(long, parse(from_os_str))]
+		cache_dir: PathBuf,
+
+		///The zCloak keeper node config file path, its queue backend receives requeued messages
+		#[structopt(long, parse(from_os_str))]
+		config: Option<PathBuf>,
+
+		///Roles of the keeper process owning the dead letters, all roles by default
+		#[structopt(long, use_delimiter = true)]
+		roles: Vec<Role>,
+
+		#[structopt(subcommand)]
+		action: DeadLetterAction,
+	},
+}
+
+#[derive(Debug, StructOpt)]
+pub enum DeadLetterAction {
+	///list dead letters of a stage, or of all stages
+	List {
+		#[structopt(long)]
+		stage: Option<Stage>,
+	},
+	///push a dead letter back to the channel of its stage
+	Requeue {
+		#[structopt(long)]
+		stage: Stage,
+		id: u64,
+	},
+	///delete a dead letter
+	Drop {
+		#[structopt(long)]
+		stage: Stage,
+		id: u64,
+	},
 }
 
 #[derive(Debug, Clone, StructOpt)]
@@ -31,18 +72,41 @@ pub struct StartOptions {
 	/// The starting block number of scanning node events.
 	#[structopt(short, long)]
 	pub start_number: Option<u64>,
+
+	///Stages run by this process, comma separated from scan, verify, attest and submit
+	#[structopt(long, use_delimiter = true)]
+	pub roles: Vec<Role>,
 }
 
 impl StartOptions {
 	pub(crate) fn channel_files(&self) -> std::result::Result<ChannelFiles, ConfigError> {
 		match &self.cache_dir {
-			Some(dir) => {
-				let event_to_ipfs = dir.join(EVENT_TO_IPFS_CHANNEL);
-				let verify_to_attest = dir.join(VERIFY_TO_ATTEST_CHANNEL);
-				let attest_to_submit = dir.join(ATTEST_TO_SUBMIT_CHANNEL);
-				Ok(ChannelFiles { event_to_ipfs, verify_to_attest, attest_to_submit })
-			},
+			Some(dir) => Ok(channel_files(dir)),
 			None => Err(ConfigError::OtherError("Fail to create channel files.".to_owned())),
 		}
 	}
+
+	pub(crate) fn database_path(&self) -> std::result::Result<PathBuf, ConfigError> {
+		match &self.cache_dir {
+			Some(dir) => Ok(database_path(dir, &self.roles)),
+			None => Err(ConfigError::OtherError("Fail to locate database directory.".to_owned())),
+		}
+	}
+}
+
+pub(crate) fn channel_files(cache_dir: &Path) -> ChannelFiles {
+	let event_to_ipfs = cache_dir.join(EVENT_TO_IPFS_CHANNEL);
+	let verify_to_attest = cache_dir.join(VERIFY_TO_ATTEST_CHANNEL);
+	let attest_to_submit = cache_dir.join(ATTEST_TO_SUBMIT_CHANNEL);
+	ChannelFiles { event_to_ipfs, verify_to_attest, attest_to_submit }
+}
+
+/// processes running a part of the roles can not share one database
+pub(crate) fn database_path(cache_dir: &Path, roles: &[Role]) -> PathBuf {
+	let roles = role::normalize(roles);
+	if roles == Role::ALL {
+		return cache_dir.join(DATABASE_DIR)
+	}
+	let names = roles.iter().map(|r| r.to_string()).collect::<Vec<_>>();
+	cache_dir.join(format!("{}-{}", DATABASE_DIR, names.join("-")))
 }
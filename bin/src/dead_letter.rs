@@ -0,0 +1,77 @@
+use std::path::{Path, PathBuf};
+
+use keeper_primitives::{
+	dead_letter::{DeadLetter, DEAD_LETTER_LOG_TARGET},
+	Config, Database, Error, QueueConfig, Stage,
+};
+
+use crate::{
+	command::{channel_files, database_path, DeadLetterAction},
+	role::Role,
+};
+
+// payload bytes shown by `list`
+const PREVIEW_LEN: usize = 120;
+
+pub async fn run(
+	cache_dir: &Path,
+	config: Option<PathBuf>,
+	roles: &[Role],
+	action: DeadLetterAction,
+) -> std::result::Result<(), Error> {
+	// the running keeper locks the database and channels
+	let database = Database::open(database_path(cache_dir, roles))?;
+	match action {
+		DeadLetterAction::List { stage } => {
+			let stages = match stage {
+				Some(stage) => vec![stage],
+				None => Stage::ALL.to_vec(),
+			};
+			for stage in stages {
+				for letter in database.dead_letters(stage)? {
+					println!("{}", summary(&letter));
+				}
+			}
+		},
+		DeadLetterAction::Requeue { stage, id } => {
+			let letter = database.dead_letter(stage, id)?.ok_or_else(|| not_found(stage, id))?;
+			// the file queue needs no config
+			let queue_config = match config {
+				Some(path) => Config::load_from_json(&path)?.queue,
+				None => QueueConfig::default(),
+			};
+			if queue_config == QueueConfig::Memory {
+				return Err(Error::OtherError("memory queues live in the keeper process".to_owned()))
+			}
+			let queue = queue_config.open(stage.input_channel(&channel_files(cache_dir))).await?;
+			queue.sender().await?.send(letter.payload).await?;
+			database.remove_dead_letter(stage, id)?;
+			database.flush()?;
+			log::info!(target: DEAD_LETTER_LOG_TARGET, "requeued {} dead letter #{}", stage, id);
+		},
+		DeadLetterAction::Drop { stage, id } => {
+			database.remove_dead_letter(stage, id)?.ok_or_else(|| not_found(stage, id))?;
+			database.flush()?;
+			log::info!(target: DEAD_LETTER_LOG_TARGET, "dropped {} dead letter #{}", stage, id);
+		},
+	}
+	Ok(())
+}
+
+fn not_found(stage: Stage, id: u64) -> Error {
+	Error::OtherError(format!("no {} dead letter #{}", stage, id))
+}
+
+fn summary(letter: &DeadLetter) -> String {
+	let payload = String::from_utf8_lossy(&letter.payload);
+	let preview: String = payload.chars().take(PREVIEW_LEN).collect();
+	format!(
+		"{} #{} | at {} | error: {} | {} bytes: {}",
+		letter.stage,
+		letter.id,
+		letter.timestamp,
+		letter.error,
+		letter.payload.len(),
+		preview
+	)
+}
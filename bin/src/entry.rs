@@ -1,33 +1,46 @@
-use log::info;
 use std::{str::FromStr, sync::Arc};
 
 use tokio::sync::RwLock;
-use yaque::{channel, recovery};
 
+use ipfs::VerifyStage;
 use keeper_primitives::{
+	attestation::{AttestationSource, AttestationSourceConfig},
 	config::Error as ConfigError,
-	ipfs::{Error as IpfsError, IPFS_LOG_TARGET},
-	kilt::{Error as KiltError, KILT_LOG_TARGET},
+	kilt::AttestationCache,
+	metrics::Metrics,
 	monitor,
-	monitor::MonitorMetrics,
-	moonbeam::{Error as MoonbeamError, MOONBEAM_SCAN_LOG_TARGET, MOONBEAM_SUBMIT_LOG_TARGET},
-	Config, ConfigInstance, Error, IpfsClient, Key, KiltClient, MoonbeamClient, SecretKeyRef, U64,
+	pipeline::Stage as _,
+	queue::Backpressure,
+	Config, ConfigInstance, Database, Error, IpfsClient, Key, KiltClient, MoonbeamClient,
+	SecretKeyRef, ShutdownTrigger,
+};
+use kilt::AttestStage;
+use moonbeam::SubmitStage;
+
+use crate::{
+	command::{
+		StartOptions, ATTEST_TO_SUBMIT_CHANNEL, EVENT_TO_IPFS_CHANNEL, VERIFY_TO_ATTEST_CHANNEL,
+	},
+	role::{Role, RoleLock},
+	tasks::{self, spawn_stage, Configs, SharedQueue, Supervisor},
 };
 
-use crate::command::StartOptions;
-
-const SLEEP_SECS: u64 = 1;
-
-// TODO move
-async fn sleep() {
-	info!("sleep for web3 error, waiting for {:} secs", SLEEP_SECS);
-	tokio::time::sleep(std::time::Duration::from_secs(SLEEP_SECS)).await;
-}
-
-pub async fn start(start_options: StartOptions) -> std::result::Result<(), Error> {
+pub async fn start(
+	start_options: StartOptions,
+	shutdown: ShutdownTrigger,
+) -> std::result::Result<(), Error> {
 	// load config
-	let start: U64 = start_options.start_number.unwrap_or_default().into();
 	let channel_files = start_options.channel_files()?;
+	let roles = crate::role::normalize(&start_options.roles);
+	// held until the keeper exits, so another process can not run the same roles
+	let mut role_locks = vec![];
+	if let Some(dir) = &start_options.cache_dir {
+		for role in &roles {
+			role_locks.push(RoleLock::acquire(dir, *role)?);
+		}
+	}
+	log::info!("[Roles] run {:?}", roles);
+	let database_path = start_options.database_path()?;
 	let config_path = start_options.config.ok_or::<Error>(
 		ConfigError::OtherError("Config File need to be specific".to_owned()).into(),
 	)?;
@@ -35,14 +48,52 @@ pub async fn start(start_options: StartOptions) -> std::result::Result<(), Error
 
 	log::info!("[Config] load successfully!");
 	// init config，
-	let moonbeam_client = MoonbeamClient::new(config.moonbeam.url)?;
-	let ipfs_client = IpfsClient::new(&config.ipfs.base_url)?;
-	let kilt_client = KiltClient::try_from_url(&config.kilt.url).await?;
+	let moonbeam_client =
+		MoonbeamClient::new(config.moonbeam.url)?.with_retry(config.moonbeam.retry.clone());
+	let ipfs_client = IpfsClient::new(&config.ipfs.base_url)?.with_retry(config.ipfs.retry);
+	let metrics = Metrics::new();
+	let kilt_client = KiltClient::try_from_url(&config.kilt.url)
+		.await?
+		.with_checkpoint(config.kilt.checkpoint.clone())
+		.with_retry(config.kilt.retry)
+		.with_metrics(metrics.clone());
+	if let Some(checkpoint) = &config.kilt.checkpoint {
+		log::info!(
+			"[Kilt] storage proofs are verified from checkpoint #{} of authority set #{}",
+			checkpoint.number,
+			checkpoint.set_id
+		);
+	}
+	let check_attestation_on_chain = config.kilt.check_on_chain;
+	let trust_policy = config.kilt.trust;
+	let database = Database::open(&database_path)?;
+	log::info!("[Database] opened at {:?}", database_path);
+	// otherwise the scan goes on from where the last run stopped
+	if let Some(start) = start_options.start_number {
+		database.set_scan_checkpoint(start.into())?;
+	}
+	let attestation_cache =
+		AttestationCache::new(config.kilt.cache, &database, config.kilt.ws_url.is_some());
 
 	let proof_contract = moonbeam_client.proof_contract(&config.moonbeam.read_contract)?;
 	let aggregator_contract =
 		moonbeam_client.aggregator_contract(&config.moonbeam.write_contract)?;
 
+	// trust policy and DID checks only apply to kilt
+	let attestation_source: Arc<dyn AttestationSource> = match &config.attestation {
+		AttestationSourceConfig::Kilt => Arc::new(kilt::KiltAttestationSource::new(
+			kilt_client.clone(),
+			attestation_cache.clone(),
+			trust_policy,
+		)),
+		AttestationSourceConfig::Evm { registry_contract } => {
+			log::info!("[Attestation] read from registry contract {}", registry_contract);
+			let registry = moonbeam_client.attestation_registry_contract(registry_contract)?;
+			let retry = config.moonbeam.retry.clone();
+			Arc::new(moonbeam::EvmAttestationSource::new(registry, retry))
+		},
+	};
+
 	let moonbeam_worker_pri = secp256k1::SecretKey::from_str(&config.moonbeam.private_key)?;
 	let key_ref = SecretKeyRef::new(&moonbeam_worker_pri);
 	let keeper_address = key_ref.address();
@@ -52,9 +103,19 @@ pub async fn start(start_options: StartOptions) -> std::result::Result<(), Error
 
 	let config_instance = ConfigInstance {
 		channel_files,
+		queue: config.queue,
+		backpressure: config.backpressure,
+		supervisor: config.supervisor,
+		metrics: metrics.clone(),
 		moonbeam_client,
 		ipfs_client,
 		kilt_client,
+		kilt_ws_url: config.kilt.ws_url,
+		attestation_cache,
+		attestation_source,
+		database,
+		policy: config.policy,
+		check_attestation_on_chain,
 		proof_contract,
 		aggregator_contract,
 		private_key: moonbeam_worker_pri,
@@ -65,202 +126,110 @@ pub async fn start(start_options: StartOptions) -> std::result::Result<(), Error
 
 	log::info!("ConfigInstance initialized");
 
+	if let Some(addr) = config.metrics.listen {
+		crate::metrics::spawn(addr, metrics, shutdown.subscribe())?;
+	}
+
 	// run a keeper
-	run(start, Arc::new(RwLock::new(config_instance))).await?;
+	run(&roles, Arc::new(RwLock::new(config_instance)), shutdown).await?;
 
 	Ok(())
 }
 
 // handle detailed process
-// todo: extract same logic to a function
 // todo: handle monitor sender error
 pub async fn run(
-	start: U64,
-	configs: Arc<RwLock<ConfigInstance>>,
+	roles: &[Role],
+	configs: Configs,
+	shutdown: ShutdownTrigger,
 ) -> std::result::Result<(), keeper_primitives::Error> {
-	// it record the latest block that contains proofevents
-	// used in ganache
-	let start = start;
-
-	// get channel files
-	let config = configs.clone();
-	let config_channels = &config.read().await.channel_files;
-
-	// force recover all channels, which delete all '.lock' files
-	recovery::unlock_queue(&config_channels.event_to_ipfs)
-		.expect("fail to unlock event2ipfs channel");
-	recovery::unlock_queue(&config_channels.verify_to_attest)
-		.expect("fail to unlock verify2attestation channel");
-	recovery::unlock_queue(&config_channels.attest_to_submit)
-		.expect("fail to unlock attestation2submit channel");
-
-	let (mut event_sender, mut event_receiver) = channel(&config_channels.event_to_ipfs).unwrap();
-	let (mut attest_sender, mut attest_receiver) =
-		channel(&config_channels.verify_to_attest).unwrap();
-	let (mut submit_sender, mut submit_receiver) =
-		channel(&config_channels.attest_to_submit).unwrap();
+	// open channels in the configured queue backend, shared by all runs of the stages
+	let config = configs.read().await;
+	let config_channels = &config.channel_files;
+	let event_queue: SharedQueue = config.queue.open(&config_channels.event_to_ipfs).await?.into();
+	let attest_queue: SharedQueue =
+		config.queue.open(&config_channels.verify_to_attest).await?.into();
+	let submit_queue: SharedQueue =
+		config.queue.open(&config_channels.attest_to_submit).await?.into();
 
 	// alert message sending
 	let (monitor_sender, mut monitor_receiver) =
 		tokio::sync::mpsc::channel::<monitor::MonitorMetrics>(100);
-
-	// spread configs
-	let config1 = configs.clone();
-	let config2 = configs.clone();
-	let config3 = configs.clone();
-	let config4 = configs.clone();
-	let config5 = configs.clone();
-
-	// spread monitors
-	let monitor_sender1 = monitor_sender.clone();
-	let monitor_sender2 = monitor_sender.clone();
-	let monitor_sender3 = monitor_sender.clone();
-	let monitor_sender4 = monitor_sender.clone();
-
-	// 1. scan moonbeam proof event, and push them to event channel
-	let task_scan = tokio::spawn(async move {
-		let config = config1.read().await;
-		let mut count = 0;
-		loop {
-			log::info!("Start Task Scan...[{}]", count);
-			count += 1;
-			let res =
-				moonbeam::task_scan(&config, &mut event_sender, start, monitor_sender1.clone())
-					.await;
-			// handle error
-			if let Err(e) = res {
-				if cfg!(feature = "monitor") {
-					let monitor_metrics = MonitorMetrics::new(
-						MOONBEAM_SCAN_LOG_TARGET.to_string(),
-						e.0,
-						&e.1,
-						config.keeper_address,
-						&config.moonbeam_client.ip_address,
-					);
-					monitor_sender1.send(monitor_metrics).await;
-				}
-
-				match e.1 {
-					// connection error, do nothing, just re scan
-					Error::MoonbeamError(MoonbeamError::Web3Error(_)) |
-					Error::MoonbeamError(MoonbeamError::Web3ContractError(_)) => {
-						// todo: make this more tolerant, e.g. retry N times first before throw and
-						// quit
-						sleep().await;
-						continue
-					},
-					_ => return e,
-				};
-			}
-		}
-	});
-
-	// 2. query ipfs and verify cid proof
-	// TODO: separate ipfs query end starksvm verify
-	let task_ipfs_verify = tokio::spawn(async move {
-		let config = config2.read().await;
-		loop {
-			let res = ipfs::task_verify(&config, (&mut attest_sender, &mut event_receiver)).await;
-			if let Err(e) = res {
-				log::error!(
-					//todo: config
-					target: "IPFS_AND_VERIFY",
-					"encounter error: {:?} in block: {:?}",
-					e.1,
-					e.0
-				);
-
-				if cfg!(feature = "monitor") {
-					let monitor_metrics = MonitorMetrics::new(
-						IPFS_LOG_TARGET.to_string(),
-						e.0,
-						&e.1,
-						config.keeper_address,
-						&config.ipfs_client.ip_address,
-					);
-					monitor_sender2.send(monitor_metrics).await;
-				}
-				// start refetching ipfs proof if connection error encountered
-				match e.1 {
-					Error::IpfsError(IpfsError::HttpError(_)) => {
-						// TODO move retry here
-						sleep().await;
-						continue
-					},
-					_ => return e,
-				};
-			}
-		}
-	});
-
-	//
-	// 3. query kilt
-	let task_kilt_attest = tokio::spawn(async move {
-		let config = config3.read().await;
-		loop {
-			let res =
-				kilt::task_attestation(&config, (&mut submit_sender, &mut attest_receiver)).await;
-
-			if let Err(e) = res {
-				log::error!(target: KILT_LOG_TARGET, "encounter error: {:?}", e);
-				if cfg!(feature = "monitor") {
-					let monitor_metrics = MonitorMetrics::new(
-						KILT_LOG_TARGET.to_string(),
-						e.0,
-						&e.1,
-						config.keeper_address,
-						&config.kilt_client.ip_address,
-					);
-					monitor_sender3.send(monitor_metrics).await;
-				}
-
-				match e.1 {
-					Error::KiltError(KiltError::KiltClientError(_e)) => {
-						// TODO need retry
-						sleep().await;
-						continue
-					},
-					_ => return e,
-				};
-			}
-		}
-	});
-
-	// 4. submit tx
-	let task_submit_tx = tokio::spawn(async move {
-		let config = config4.read().await;
-
-		loop {
-			let res =
-				moonbeam::task_submit(&config, &mut submit_receiver, monitor_sender4.clone()).await;
-			if let Err(e) = res {
-				if cfg!(feature = "monitor") {
-					let monitor_metrics = MonitorMetrics::new(
-						MOONBEAM_SUBMIT_LOG_TARGET.to_string(),
-						e.0,
-						&e.1,
-						config.keeper_address,
-						&config.moonbeam_client.ip_address,
-					);
-					monitor_sender4.send(monitor_metrics).await;
-				}
-
-				match e.1 {
-					Error::MoonbeamError(MoonbeamError::Web3Error(_)) |
-					Error::MoonbeamError(MoonbeamError::Web3ContractError(_)) => {
-						// todo need retry
-						sleep().await;
-						continue
-					},
-					_ => return e,
-				};
-			}
+	let mut supervisor = Supervisor::new(
+		config.supervisor.clone(),
+		config.keeper_address,
+		monitor_sender.clone(),
+		shutdown.clone(),
+	);
+
+	// each queue side belongs to one role, and the role lock is held, so a lock left on
+	// our sides is from a crashed keeper. a failed run drops its ends, releasing the locks
+	// for the restart
+	if roles.contains(&Role::Scan) {
+		event_queue.unlock_sender()?;
+		// every channel is downstream of the scan
+		let limits = &config.backpressure;
+		let backpressure = Backpressure::new(limits.check_interval_secs, config.metrics.clone())
+			.watch(EVENT_TO_IPFS_CHANNEL, event_queue.clone(), limits.event_to_ipfs)
+			.watch(VERIFY_TO_ATTEST_CHANNEL, attest_queue.clone(), limits.verify_to_attest)
+			.watch(ATTEST_TO_SUBMIT_CHANNEL, submit_queue.clone(), limits.attest_to_submit);
+		let (configs, queue, monitor) =
+			(configs.clone(), event_queue.clone(), monitor_sender.clone());
+		let shutdown = shutdown.subscribe();
+		supervisor.add("scan", &config.moonbeam_client.ip_address, move || {
+			let (configs, queue, monitor) = (configs.clone(), queue.clone(), monitor.clone());
+			tasks::spawn_scan(configs, queue, backpressure.clone(), monitor, shutdown.clone())
+		});
+	}
+	if roles.contains(&Role::Verify) {
+		event_queue.unlock_receiver()?;
+		attest_queue.unlock_sender()?;
+		let configs = configs.clone();
+		let (input, output) = (event_queue.clone(), attest_queue.clone());
+		let shutdown = shutdown.subscribe();
+		supervisor.add("verify", VerifyStage.endpoint(&config), move || {
+			let (input, output) = (input.clone(), Some(output.clone()));
+			spawn_stage(VerifyStage, configs.clone(), input, output, shutdown.clone())
+		});
+	}
+	if roles.contains(&Role::Attest) {
+		attest_queue.unlock_receiver()?;
+		submit_queue.unlock_sender()?;
+		let configs = configs.clone();
+		let (input, output) = (attest_queue.clone(), submit_queue.clone());
+		let shutdown = shutdown.subscribe();
+		supervisor.add("attest", AttestStage.endpoint(&config), move || {
+			let (input, output) = (input.clone(), Some(output.clone()));
+			spawn_stage(AttestStage, configs.clone(), input, output, shutdown.clone())
+		});
+	}
+	if roles.contains(&Role::Submit) {
+		submit_queue.unlock_receiver()?;
+		// read again after every submission
+		moonbeam::update_keeper_balance(&config).await;
+		let (configs, input) = (configs.clone(), submit_queue.clone());
+		let shutdown = shutdown.subscribe();
+		supervisor.add("submit", SubmitStage.endpoint(&config), move || {
+			spawn_stage(SubmitStage, configs.clone(), input.clone(), None, shutdown.clone())
+		});
+	}
+	// the watcher reads attestations approved by the submitter from the database of its
+	// process, and invalidates the attestation cache the attester reads
+	if roles.contains(&Role::Attest) || roles.contains(&Role::Submit) {
+		if let Some(ws_url) = &config.kilt_ws_url {
+			let (configs, monitor) = (configs.clone(), monitor_sender.clone());
+			let shutdown = shutdown.subscribe();
+			supervisor.add("revocation watch", ws_url, move || {
+				tasks::spawn_revocation_watch(configs.clone(), monitor.clone(), shutdown.clone())
+			});
 		}
-	});
+	}
+	drop(config);
 
 	// monitor
+	let config = configs.clone();
 	let task_monitor_handle = tokio::spawn(async move {
-		let config = config5.read().await;
+		let config = config.read().await;
 		while let Some(msg) = monitor_receiver.recv().await {
 			#[cfg(feature = "monitor")]
 			{
@@ -271,13 +240,12 @@ pub async fn run(
 		}
 	});
 
-	// all tasks will loop so no need to handle Ok condition
-	tokio::try_join!(
-		task_scan,
-		task_ipfs_verify,
-		task_kilt_attest,
-		task_submit_tx,
-		task_monitor_handle
-	)?;
-	Ok(())
+	// tasks are restarted until a shutdown, or until one fails too often
+	let res = tokio::select! {
+		res = supervisor.run() => res.map_err(|e| e.error),
+		res = task_monitor_handle => Ok(res?),
+	};
+	// the scan checkpoint and the records of in-flight requests are on disk before exit
+	configs.read().await.database.flush()?;
+	res
 }
@@ -3,10 +3,13 @@ use futures::FutureExt;
 use structopt::StructOpt;
 
 use command::Opt;
-use keeper_primitives::Error;
+use keeper_primitives::{Error, ShutdownTrigger};
 
 mod command;
+mod dead_letter;
 mod entry;
+mod metrics;
+mod role;
 mod runner;
 mod tasks;
 
@@ -19,10 +22,13 @@ async fn main() -> std::result::Result<(), Error> {
 	let opt = Opt::from_args();
 	match opt {
 		Opt::Start { options } => {
-			let f = entry::start(options);
+			let shutdown = ShutdownTrigger::new();
+			let f = entry::start(options, shutdown.clone());
 			let f = f.fuse();
-			runner::run_until_exit(f).await?;
+			runner::run_until_exit(f, shutdown).await?;
 		},
+		Opt::DeadLetter { cache_dir, config, roles, action } =>
+			dead_letter::run(&cache_dir, config, &roles, action).await?,
 	}
 	Ok(())
 }
@@ -0,0 +1,52 @@
+use std::{convert::Infallible, net::SocketAddr};
+
+use hyper::{
+	header::{HeaderValue, CONTENT_TYPE},
+	service::{make_service_fn, service_fn},
+	Body, Method, Request, Response, Server, StatusCode,
+};
+
+use keeper_primitives::{
+	metrics::{Metrics, METRICS_LOG_TARGET},
+	Error, Shutdown,
+};
+
+// prometheus text exposition format
+const TEXT_FORMAT: &str = "text/plain; version=0.0.4";
+
+/// serve `/metrics` on `addr` until a shutdown is requested, fails if the address is taken
+pub fn spawn(addr: SocketAddr, metrics: Metrics, mut shutdown: Shutdown) -> Result<(), Error> {
+	let make_service = make_service_fn(move |_| {
+		let metrics = metrics.clone();
+		async move {
+			Ok::<_, Infallible>(service_fn(move |req| {
+				let res = respond(&metrics, req);
+				async move { Ok::<_, Infallible>(res) }
+			}))
+		}
+	});
+	let server = Server::try_bind(&addr)
+		.map_err(|e| Error::OtherError(format!("fail to serve metrics on {}, err: {}", addr, e)))?
+		.serve(make_service)
+		.with_graceful_shutdown(async move { shutdown.requested().await });
+	log::info!(target: METRICS_LOG_TARGET, "serve metrics at http://{}/metrics", addr);
+
+	tokio::spawn(async move {
+		if let Err(e) = server.await {
+			log::error!(target: METRICS_LOG_TARGET, "metrics server stopped, err: {}", e);
+		}
+	});
+	Ok(())
+}
+
+fn respond(metrics: &Metrics, req: Request<Body>) -> Response<Body> {
+	let mut res = Response::default();
+	match (req.method(), req.uri().path()) {
+		(&Method::GET, "/metrics") => {
+			res.headers_mut().insert(CONTENT_TYPE, HeaderValue::from_static(TEXT_FORMAT));
+			*res.body_mut() = Body::from(metrics.encode());
+		},
+		_ => *res.status_mut() = StatusCode::NOT_FOUND,
+	}
+	res
+}
@@ -0,0 +1,129 @@
+use std::{
+	fmt,
+	fs::{self, File, OpenOptions},
+	io::{Read, Seek, SeekFrom, Write},
+	path::Path,
+	str::FromStr,
+};
+
+use fs2::FileExt;
+use keeper_primitives::Error;
+
+/// stages a keeper process runs, processes of different roles share the queues
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Role {
+	// scan moonbeam proof events
+	Scan,
+	// fetch proofs from ipfs and verify them
+	Verify,
+	// check attestations on kilt, and watch revocations of cached ones
+	Attest,
+	// submit verdicts with the keeper key, and watch revocations of approved ones
+	Submit,
+}
+
+impl Role {
+	pub const ALL: [Role; 4] = [Role::Scan, Role::Verify, Role::Attest, Role::Submit];
+}
+
+impl fmt::Display for Role {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Role::Scan => write!(f, "scan"),
+			Role::Verify => write!(f, "verify"),
+			Role::Attest => write!(f, "attest"),
+			Role::Submit => write!(f, "submit"),
+		}
+	}
+}
+
+impl FromStr for Role {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s {
+			"scan" => Ok(Role::Scan),
+			"verify" => Ok(Role::Verify),
+			"attest" => Ok(Role::Attest),
+			"submit" => Ok(Role::Submit),
+			_ => Err(format!("unknown role {}, expect one of scan, verify, attest, submit", s)),
+		}
+	}
+}
+
+/// all roles if none is given, sorted and deduplicated
+pub fn normalize(roles: &[Role]) -> Vec<Role> {
+	if roles.is_empty() {
+		return Role::ALL.to_vec()
+	}
+	let mut roles = roles.to_vec();
+	roles.sort();
+	roles.dedup();
+	roles
+}
+
+/// file in the cache dir locked exclusively while a process runs the role. the OS
+/// releases the lock when the process dies, the file only tells the pid of the holder
+#[derive(Debug)]
+pub struct RoleLock {
+	file: File,
+}
+
+impl RoleLock {
+	/// fails if another process holds the role
+	pub fn acquire(cache_dir: &Path, role: Role) -> Result<Self, Error> {
+		let path = cache_dir.join(format!("{}.lock", role));
+		fs::create_dir_all(cache_dir)?;
+		// never truncated before the lock is held, the holder's pid stays readable
+		let mut file = OpenOptions::new().read(true).write(true).create(true).open(&path)?;
+		if file.try_lock_exclusive().is_err() {
+			let mut owner = String::new();
+			file.read_to_string(&mut owner)?;
+			return Err(Error::OtherError(format!(
+				"role {} is held by process {}, see {:?}",
+				role,
+				owner.trim(),
+				path
+			)))
+		}
+		file.set_len(0)?;
+		file.seek(SeekFrom::Start(0))?;
+		write!(file, "{}", std::process::id())?;
+		Ok(RoleLock { file })
+	}
+}
+
+impl Drop for RoleLock {
+	fn drop(&mut self) {
+		// the file is kept, removing it would let a new process lock another inode
+		let _ = FileExt::unlock(&self.file);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{normalize, Role, RoleLock};
+
+	#[test]
+	fn roles_should_default_to_all() {
+		assert_eq!(normalize(&[]), Role::ALL.to_vec());
+		let roles = normalize(&[Role::Submit, Role::Scan, Role::Submit]);
+		assert_eq!(roles, vec![Role::Scan, Role::Submit]);
+		assert_eq!("attest".parse::<Role>(), Ok(Role::Attest));
+		assert!("ipfs".parse::<Role>().is_err());
+	}
+
+	#[test]
+	fn role_lock_should_be_exclusive() {
+		let dir = std::env::temp_dir().join(format!("keeper-role-{}", std::process::id()));
+		let lock = RoleLock::acquire(&dir, Role::Verify).unwrap();
+		assert!(RoleLock::acquire(&dir, Role::Verify).is_err());
+		assert!(RoleLock::acquire(&dir, Role::Submit).is_ok());
+		drop(lock);
+
+		// a file left by a dead owner is not locked
+		std::fs::write(dir.join("verify.lock"), u32::MAX.to_string()).unwrap();
+		assert!(RoleLock::acquire(&dir, Role::Verify).is_ok());
+		let _ = std::fs::remove_dir_all(&dir);
+	}
+}
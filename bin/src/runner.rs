@@ -1,9 +1,14 @@
 use futures::{future, future::FutureExt, pin_mut, select, Future};
 
+use keeper_primitives::ShutdownTrigger;
+
 use super::Error;
 
+/// run `func` until it returns. the first SIGINT or SIGTERM asks it to shut down and waits
+/// for it, the second one exits right away with an error. a shutdown `func` requested itself
+/// does not count as a signal
 #[cfg(target_family = "unix")]
-pub async fn run_until_exit<F>(func: F) -> std::result::Result<(), Error>
+pub async fn run_until_exit<F>(func: F, shutdown: ShutdownTrigger) -> std::result::Result<(), Error>
 where
 	F: Future<Output = std::result::Result<(), Error>> + future::FusedFuture,
 {
@@ -12,16 +17,33 @@ where
 	let mut stream_int = signal(SignalKind::interrupt())?;
 	let mut stream_term = signal(SignalKind::terminate())?;
 
-	let t1 = stream_int.recv().fuse();
-	let t2 = stream_term.recv().fuse();
 	let t3 = func;
+	pin_mut!(t3);
 
-	pin_mut!(t1, t2, t3);
+	let mut signals = 0;
+	loop {
+		let t1 = stream_int.recv().fuse();
+		let t2 = stream_term.recv().fuse();
+		pin_mut!(t1, t2);
 
-	select! {
-		_ = t1 => {},
-		_ = t2 => {},
-		res = t3 => res?,
+		select! {
+			_ = t1 => {},
+			_ = t2 => {},
+			res = t3 => {
+				res?;
+				break
+			},
+		}
+
+		signals += 1;
+		if signals > 1 {
+			log::warn!("zCloak-Keeper forced to exit, in-flight work is dropped.");
+			return Err(Error::OtherError(
+				"forced to exit before in-flight work finished".to_owned(),
+			))
+		}
+		log::info!("zCloak-Keeper shutting down after in-flight work, signal again to force exit.");
+		shutdown.request();
 	}
 
 	log::info!("zCloak-Keeper Exit Normally.");
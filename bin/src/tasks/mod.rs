@@ -1 +1,114 @@
+use std::sync::Arc;
 
+use tokio::{sync::RwLock, task::JoinHandle};
+
+use keeper_primitives::{
+	kilt::KILT_LOG_TARGET,
+	monitor::MonitorSender,
+	moonbeam::MOONBEAM_SCAN_LOG_TARGET,
+	pipeline::{self, Stage},
+	queue::{Backpressure, Queue},
+	ConfigInstance, Error, KeeperError, Result, Shutdown,
+};
+
+mod supervisor;
+
+pub use supervisor::Supervisor;
+
+pub type Configs = Arc<RwLock<ConfigInstance>>;
+// tasks end with `Ok` once a shutdown is requested, and return any other error to the
+// supervisor, which restarts them or shuts the keeper down
+pub type StageHandle = JoinHandle<Result<()>>;
+// channels are shared by the runs of a task, each run opens its own ends
+pub type SharedQueue = Arc<dyn Queue>;
+
+// the supervisor alerts, the task only logs under its own target
+fn log_error(target: &'static str, e: KeeperError) -> KeeperError {
+	log::error!(target: target, "encounter {:?} error: {}", e.kind(), e);
+	e
+}
+
+/// run a stage until a shutdown is requested or it fails
+pub fn spawn_stage<S: Stage + 'static>(
+	stage: S,
+	configs: Configs,
+	input: SharedQueue,
+	output: Option<SharedQueue>,
+	shutdown: Shutdown,
+) -> StageHandle {
+	tokio::spawn(run_stage_task(stage, configs, input, output, shutdown))
+}
+
+async fn run_stage_task<S: Stage>(
+	stage: S,
+	configs: Configs,
+	input: SharedQueue,
+	output: Option<SharedQueue>,
+	shutdown: Shutdown,
+) -> Result<()> {
+	let mut receiver = input.receiver().await?;
+	let mut sender = match &output {
+		Some(queue) => Some(queue.sender().await?),
+		None => None,
+	};
+	let config = configs.read().await;
+	pipeline::run_stage(&stage, &config, &mut receiver, sender.as_mut(), &shutdown)
+		.await
+		.map_err(|e| log_error(S::LOG_TARGET, e))?;
+	// an uncommitted message is delivered again to the next keeper
+	log::info!(target: S::LOG_TARGET, "{} stage stopped", S::KIND);
+	Ok(())
+}
+
+/// scan moonbeam proof events, and push them to the event channel
+pub fn spawn_scan(
+	configs: Configs,
+	event_queue: SharedQueue,
+	backpressure: Backpressure,
+	monitor_sender: MonitorSender,
+	shutdown: Shutdown,
+) -> StageHandle {
+	tokio::spawn(run_scan_task(configs, event_queue, backpressure, monitor_sender, shutdown))
+}
+
+async fn run_scan_task(
+	configs: Configs,
+	event_queue: SharedQueue,
+	mut backpressure: Backpressure,
+	monitor_sender: MonitorSender,
+	shutdown: Shutdown,
+) -> Result<()> {
+	let mut event_sender = event_queue.sender().await?;
+	let config = configs.read().await;
+	log::info!("Start Task Scan...");
+	moonbeam::task_scan(&config, &mut event_sender, &mut backpressure, monitor_sender, &shutdown)
+		.await
+		.map_err(|e| log_error(MOONBEAM_SCAN_LOG_TARGET, e))
+}
+
+/// watch approved attestations for revocation, only with a kilt websocket endpoint
+pub fn spawn_revocation_watch(
+	configs: Configs,
+	monitor_sender: MonitorSender,
+	shutdown: Shutdown,
+) -> StageHandle {
+	tokio::spawn(run_revocation_watch_task(configs, monitor_sender, shutdown))
+}
+
+async fn run_revocation_watch_task(
+	configs: Configs,
+	monitor_sender: MonitorSender,
+	mut shutdown: Shutdown,
+) -> Result<()> {
+	let config = configs.read().await;
+	let ws_url = match &config.kilt_ws_url {
+		Some(url) => url,
+		None => return Err(Error::OtherError("no kilt websocket endpoint".to_owned()).into()),
+	};
+	// nothing in flight is worth waiting for, the subscription is dropped right away
+	tokio::select! {
+		res = kilt::task_revocation_watch(&config, ws_url, monitor_sender) =>
+			res.map_err(|e| log_error(KILT_LOG_TARGET, e)),
+		_ = shutdown.requested() => Ok(()),
+	}
+}
@@ -0,0 +1,252 @@
+use std::{
+	collections::VecDeque,
+	future::Future,
+	pin::Pin,
+	sync::Arc,
+	task::{Context, Poll},
+	time::{Duration, Instant},
+};
+
+use futures::{stream::FuturesUnordered, StreamExt};
+use tokio::task::JoinError;
+
+use keeper_primitives::{
+	monitor::{MonitorMetrics, MonitorSender},
+	Address, ErrorKind, KeeperError, Result, Shutdown, ShutdownTrigger, SupervisorConfig,
+};
+
+use super::StageHandle;
+
+pub const SUPERVISOR_LOG_TARGET: &str = "Supervisor";
+
+type Spawn = Arc<dyn Fn() -> StageHandle + Send + Sync>;
+
+struct Task {
+	name: &'static str,
+	// shown in monitor alerts
+	endpoint: String,
+	spawn: Spawn,
+	// when the task failed within the window, oldest first
+	failures: VecDeque<Instant>,
+}
+
+/// restarts tasks stopped by an error, and shuts the keeper down once one fails too often
+/// or with an error a restart can not fix
+pub struct Supervisor {
+	config: SupervisorConfig,
+	keeper_address: Address,
+	monitor_sender: MonitorSender,
+	shutdown: ShutdownTrigger,
+	tasks: Vec<Task>,
+}
+
+impl Supervisor {
+	pub fn new(
+		config: SupervisorConfig,
+		keeper_address: Address,
+		monitor_sender: MonitorSender,
+		shutdown: ShutdownTrigger,
+	) -> Self {
+		Supervisor { config, keeper_address, monitor_sender, shutdown, tasks: vec![] }
+	}
+
+	/// `spawn` starts a fresh run of the task, it is called again on every restart
+	pub fn add<F>(&mut self, name: &'static str, endpoint: &str, spawn: F)
+	where
+		F: Fn() -> StageHandle + Send + Sync + 'static,
+	{
+		let endpoint = endpoint.to_owned();
+		self.tasks
+			.push(Task { name, endpoint, spawn: Arc::new(spawn), failures: VecDeque::new() });
+	}
+
+	/// run the tasks until all of them stopped on a shutdown. returns the fatal error of a
+	/// task, or the error of the task failing more than `max_restarts` times within the
+	/// window, a shutdown is requested then
+	pub async fn run(mut self) -> Result<()> {
+		let shutdown = self.shutdown.subscribe();
+		let mut running = FuturesUnordered::new();
+		for (index, task) in self.tasks.iter().enumerate() {
+			running.push(run_task(index, task.spawn.clone(), Duration::ZERO, shutdown.clone()));
+		}
+
+		let window = Duration::from_secs(self.config.window_secs);
+		let mut escalated = None;
+		while let Some((index, res)) = running.next().await {
+			let (e, fatal) = match res {
+				Ok(Ok(())) => continue,
+				Ok(Err(e)) => {
+					let fatal = e.kind() == ErrorKind::Fatal;
+					(e, fatal)
+				},
+				// a panic counts as a failure too, the next run may not hit it
+				Err(e) => (KeeperError::from(e), false),
+			};
+			// nothing is restarted once the keeper is stopping
+			if self.shutdown.is_requested() {
+				let name = self.tasks[index].name;
+				log::error!(target: SUPERVISOR_LOG_TARGET, "{} failed stopping, err: {}", name, e);
+				continue
+			}
+			let task = &mut self.tasks[index];
+			let now = Instant::now();
+			task.failures.push_back(now);
+			while matches!(task.failures.front(), Some(t) if now.duration_since(*t) > window) {
+				task.failures.pop_front();
+			}
+			let failures = task.failures.len() as u32;
+			let (name, endpoint) = (task.name, task.endpoint.clone());
+
+			if fatal {
+				log::error!(
+					target: SUPERVISOR_LOG_TARGET,
+					"{} failed with a fatal error, shut down the keeper, err: {}",
+					name,
+					e
+				);
+				self.alert(&endpoint, &e).await;
+				self.shutdown.request();
+				escalated = Some(e);
+				continue
+			}
+			if failures > self.config.max_restarts {
+				log::error!(
+					target: SUPERVISOR_LOG_TARGET,
+					"{} failed {} times in {:?}, shut down the keeper, err: {}",
+					name,
+					failures,
+					window,
+					e
+				);
+				self.alert(&endpoint, &e).await;
+				self.shutdown.request();
+				escalated = Some(e);
+				continue
+			}
+
+			let delay = self.config.backoff.delay(failures);
+			log::warn!(
+				target: SUPERVISOR_LOG_TARGET,
+				"{} failed, restart {}/{} in {:?}, err: {}",
+				name,
+				failures,
+				self.config.max_restarts,
+				delay,
+				e
+			);
+			self.alert(&endpoint, &e).await;
+			let spawn = self.tasks[index].spawn.clone();
+			running.push(run_task(index, spawn, delay, shutdown.clone()));
+		}
+
+		match escalated {
+			Some(e) => Err(e),
+			None => Ok(()),
+		}
+	}
+
+	async fn alert(&self, endpoint: &str, e: &KeeperError) {
+		if cfg!(feature = "monitor") {
+			let target = SUPERVISOR_LOG_TARGET.to_string();
+			let monitor_metrics = MonitorMetrics::new(target, e, self.keeper_address, endpoint);
+			let _ = self.monitor_sender.send(monitor_metrics).await;
+		}
+	}
+}
+
+// start the task after `delay`, resolves once it stopped. not started on a shutdown
+async fn run_task(
+	index: usize,
+	spawn: Spawn,
+	delay: Duration,
+	mut shutdown: Shutdown,
+) -> (usize, std::result::Result<Result<()>, JoinError>) {
+	tokio::select! {
+		_ = tokio::time::sleep(delay) => {},
+		_ = shutdown.requested() => return (index, Ok(Ok(()))),
+	}
+	(index, AbortOnDrop(spawn()).await)
+}
+
+// tasks are cancelled with the supervisor, e.g. on a forced exit
+struct AbortOnDrop(StageHandle);
+
+impl Future for AbortOnDrop {
+	type Output = std::result::Result<Result<()>, JoinError>;
+
+	fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+		Pin::new(&mut self.0).poll(cx)
+	}
+}
+
+impl Drop for AbortOnDrop {
+	fn drop(&mut self) {
+		self.0.abort();
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::sync::{
+		atomic::{AtomicUsize, Ordering},
+		Arc,
+	};
+
+	use keeper_primitives::{
+		kilt::Error as KiltError, Address, Error, RetryPolicy, ShutdownTrigger, SupervisorConfig,
+	};
+
+	use super::Supervisor;
+
+	fn supervisor(max_restarts: u32, trigger: &ShutdownTrigger) -> Supervisor {
+		let backoff = RetryPolicy { base_delay_ms: 1, max_delay_ms: 1, ..Default::default() };
+		let config = SupervisorConfig { max_restarts, window_secs: 60, backoff };
+		let (monitor_sender, _monitor_receiver) = tokio::sync::mpsc::channel(10);
+		Supervisor::new(config, Address::default(), monitor_sender, trigger.clone())
+	}
+
+	#[tokio::test]
+	async fn supervisor_should_shut_down_after_max_restarts() {
+		let trigger = ShutdownTrigger::new();
+		let mut supervisor = supervisor(2, &trigger);
+
+		let runs = Arc::new(AtomicUsize::new(0));
+		let failing = runs.clone();
+		supervisor.add("failing", "endpoint", move || {
+			failing.fetch_add(1, Ordering::SeqCst);
+			tokio::spawn(async { Err(KiltError::SubscriptionClosed.into()) })
+		});
+		// runs until the shutdown
+		let shutdown = trigger.subscribe();
+		supervisor.add("healthy", "endpoint", move || {
+			let mut shutdown = shutdown.clone();
+			tokio::spawn(async move {
+				shutdown.requested().await;
+				Ok(())
+			})
+		});
+
+		let e = supervisor.run().await.unwrap_err();
+		assert_eq!(runs.load(Ordering::SeqCst), 3);
+		assert!(matches!(e.error, Error::KiltError(KiltError::SubscriptionClosed)));
+		assert!(trigger.is_requested());
+	}
+
+	#[tokio::test]
+	async fn supervisor_should_not_restart_fatal_errors() {
+		let trigger = ShutdownTrigger::new();
+		let mut supervisor = supervisor(5, &trigger);
+
+		let runs = Arc::new(AtomicUsize::new(0));
+		let failing = runs.clone();
+		supervisor.add("broken", "endpoint", move || {
+			failing.fetch_add(1, Ordering::SeqCst);
+			tokio::spawn(async { Err(KiltError::UrlFormatError("http://".to_owned()).into()) })
+		});
+
+		let e = supervisor.run().await.unwrap_err();
+		assert_eq!(runs.load(Ordering::SeqCst), 1);
+		assert!(matches!(e.error, Error::KiltError(KiltError::UrlFormatError(_))));
+		assert!(trigger.is_requested());
+	}
+}
@@ -1,25 +1,45 @@
+use std::time::Instant;
+
 use keeper_primitives::{
 	ipfs::{IpfsClient, IPFS_LOG_TARGET},
+	metrics::{Metrics, IPFS_SERVICE},
 	moonbeam::ProofEvent,
-	verify::{verify_proof, Result, VERIFY_LOG_TARGET},
-	Events, Result as KeeperResult, VerifyResult,
+	verify::{verify_cache_key, verify_proof, Result, VERIFY_LOG_TARGET},
+	Database, ErrorContext, Events, Result as KeeperResult, Retryable, Verdict, VerifyResult,
 };
-pub use task::task_verify;
+pub use task::VerifyStage;
 
 mod task;
 
 // empty return is set to none
 pub async fn query_and_verify(
 	ipfs: &IpfsClient,
+	db: &Database,
+	metrics: &Metrics,
 	input: Events,
 ) -> KeeperResult<Option<Vec<VerifyResult>>> {
 	log::info!(target: IPFS_LOG_TARGET, "start querying ipfs");
 	let mut ret = vec![];
 	for proof in input {
-		let cid_context = ipfs
-			.fetch_proof(proof.proof_cid())
-			.await
-			.map_err(|e| (proof.block_number(), e.into()))?;
+		let fetch = ipfs.fetch_proof(proof.proof_cid());
+		let cid_context = match metrics.observe_request(IPFS_SERVICE, fetch).await {
+			Ok(c) => c,
+			// ipfs is out of reach, leave the message in the channel to be redelivered
+			Err(e) if e.is_retryable() =>
+				return Err(e).with_cid(proof.proof_cid()).at_block(proof.block_number()),
+			Err(e) => {
+				// a missing proof should not block the following ones
+				log::error!(
+					target: IPFS_LOG_TARGET,
+					"fail to fetch proof|e:{:?}|event_blocknumber:{:?}|cid:{:}",
+					e,
+					proof.block_number(),
+					proof.proof_cid(),
+				);
+				ret.push(VerifyResult::new_from_proof_event(proof, Verdict::ProofUnavailable));
+				continue
+			},
+		};
 		log::info!(
 			target: IPFS_LOG_TARGET,
 			"ipfs proof of data owner {:} in block {:?} fetched and the content length is {}",
@@ -28,7 +48,10 @@ pub async fn query_and_verify(
 			cid_context.len()
 		);
 		// if verify meet error, do not throw it.
-		let result = match verify(&proof, &cid_context) {
+		let started = Instant::now();
+		let res = cached_verify(db, &proof, &cid_context);
+		metrics.observe_verification(res.as_ref().ok().copied(), started.elapsed());
+		let verdict = match res {
 			Ok(r) => {
 				log::info!(
 					target: VERIFY_LOG_TARGET,
@@ -37,10 +60,11 @@ pub async fn query_and_verify(
 					proof.proof_cid(),
 					r
 				);
-				if !r {
-					// TODO set to database in future
+				if r {
+					Verdict::Valid
+				} else {
+					Verdict::ProofInvalid
 				}
-				r
 			},
 			Err(e) => {
 				log::error!(
@@ -50,11 +74,11 @@ pub async fn query_and_verify(
 					&proof.block_number(),
 					proof.proof_cid(),
 				);
-				false
+				e.verdict()
 			},
 		};
 
-		ret.push(VerifyResult::new_from_proof_event(proof, result));
+		ret.push(VerifyResult::new_from_proof_event(proof, verdict));
 	}
 
 	if ret.is_empty() {
@@ -64,6 +88,46 @@ pub async fn query_and_verify(
 	}
 }
 
+// same proof content with same inputs always gets the same verdict,
+// so look up the local cache before running the StarksVM verifier
+pub(crate) fn cached_verify(db: &Database, p: &ProofEvent, context: &[u8]) -> Result<bool> {
+	let key = verify_cache_key(&p.program_hash(), context, p.public_inputs(), &p.outputs());
+	match db.verify_result(&key) {
+		Ok(Some(r)) => {
+			log::info!(
+				target: VERIFY_LOG_TARGET,
+				"[STARKVM] verification cache hit for proof {:?} in block {:?} | key: {:} | cached as {}",
+				p.proof_cid(),
+				p.block_number(),
+				hex::encode(key),
+				r
+			);
+			return Ok(r)
+		},
+		Ok(None) => {},
+		Err(e) => {
+			log::warn!(
+				target: VERIFY_LOG_TARGET,
+				"fail to read verification cache, verify proof {:?} directly, err: {:?}",
+				p.proof_cid(),
+				e
+			);
+		},
+	}
+
+	let r = verify(p, context)?;
+	// only cache finished verification, inner errors may be resolved next time
+	if let Err(e) = db.insert_verify_result(&key, r) {
+		log::warn!(
+			target: VERIFY_LOG_TARGET,
+			"fail to write verification cache for proof {:?}, err: {:?}",
+			p.proof_cid(),
+			e
+		);
+	}
+	Ok(r)
+}
+
 pub(crate) fn verify(p: &ProofEvent, context: &[u8]) -> Result<bool> {
 	let inputs = p.public_inputs();
 	let outputs = p.outputs();
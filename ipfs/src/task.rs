@@ -1,59 +1,33 @@
-use std::time::Duration;
+use async_trait::async_trait;
 
 use keeper_primitives::{
-	ConfigInstance, Delay, Error, Events, JsonParse, MqReceiver, MqSender, CHANNEL_LOG_TARGET,
-	MESSAGE_PARSE_LOG_TARGET, U64,
+	ipfs::IPFS_LOG_TARGET, moonbeam::ProofEvent, pipeline, ConfigInstance, Result, Stage,
+	VerifyResult,
 };
 
-// todo: get block number in error return
-pub async fn task_verify(
-	config: &ConfigInstance,
-	msg_queue: (&mut MqSender, &mut MqReceiver),
-) -> std::result::Result<(), (Option<U64>, Error)> {
-	while let Ok(events) = msg_queue.1.recv_timeout(Delay::new(Duration::from_secs(1))).await {
-		let events = match events {
-			Some(a) => a,
-			None => continue,
-		};
+/// fetch proofs of scanned events from ipfs and verify them
+#[derive(Clone, Copy, Debug, Default)]
+pub struct VerifyStage;
 
-		log::info!(target: CHANNEL_LOG_TARGET, "recv msg in task2");
+#[async_trait]
+impl pipeline::Stage for VerifyStage {
+	type Input = ProofEvent;
+	type Output = VerifyResult;
 
-		// parse event from str to ProofEvent
-		let inputs = Events::try_from_bytes(&*events);
-		let inputs = match inputs {
-			Ok(r) => r,
-			Err(e) => {
-				// log error
-				log::error!(
-					target: MESSAGE_PARSE_LOG_TARGET,
-					"event messages in ipfs component wrongly parsed, {:?}",
-					e
-				);
-				return Err((None, e.into()))
-			},
-		};
+	const KIND: Stage = Stage::Ipfs;
+	const LOG_TARGET: &'static str = IPFS_LOG_TARGET;
 
-		let res = super::query_and_verify(&config.ipfs_client, inputs).await?;
-		// not empty
-		if res.is_some() {
-			// todo : ugly hacking
-			let start = res.clone().unwrap().first().unwrap().number;
-			let status =
-				msg_queue.0.send(serde_json::to_vec(&res).map_err(|e| (start, e.into()))?).await;
-
-			match status {
-				Ok(_) => {
-					// delete events in channel after the events are successfully
-					// transformed and pushed into
-					events.commit().map_err(|e| (start, e.into()))?;
-				},
-				Err(e) => {
-					log::error!("in task2 send to queue error:{:?}", e);
-					return Err((None, e.into()))
-				},
-			}
-		}
+	fn endpoint<'a>(&self, config: &'a ConfigInstance) -> &'a str {
+		&config.ipfs_client.ip_address
 	}
 
-	Ok(())
+	async fn process(
+		&self,
+		config: &ConfigInstance,
+		inputs: Vec<ProofEvent>,
+	) -> Result<Vec<VerifyResult>> {
+		let (ipfs, metrics) = (&config.ipfs_client, &config.metrics);
+		let res = super::query_and_verify(ipfs, &config.database, metrics, inputs).await?;
+		Ok(res.unwrap_or_default())
+	}
 }
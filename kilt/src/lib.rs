@@ -1,101 +1,371 @@
-use jsonrpsee::types::Error as RpcError;
+use std::collections::HashMap;
 
 use keeper_primitives::{
 	kilt::{
-		get_attestation_storage_key, Attestation, Error, KiltClient, KILT_LOG_TARGET,
-		KILT_MAX_RETRY_TIMES,
+		did_storage_keys, get_attestation_storage_key, AccountId, Attestation, AttestationCache,
+		DidIdentity, Error, KiltClient, TrustPolicy, KILT_LOG_TARGET,
 	},
-	Decode, Hash, Result, VerifyResult,
+	metrics::KILT_SERVICE,
+	moonbeam::{Error as MoonbeamError, CHECK_ATTESTATION},
+	Contract, Decode, ErrorContext, Hash, Http, Result, Retryable, StorageData, StorageKey,
+	Verdict, VerifyResult, Web3Options,
 };
-pub use task::task_attestation;
+pub use source::KiltAttestationSource;
+pub use task::AttestStage;
+pub use trust::{resolve_trust, TrustChain};
+pub use watch::task_revocation_watch;
 
+mod source;
 mod task;
+mod trust;
+mod watch;
+
+// check the credential behind every verified proof, the verdict is downgraded
+// if the attestation is missing, revoked or not trusted by the policy
+pub async fn filter(
+	client: &KiltClient,
+	cache: &AttestationCache,
+	trust_policy: &TrustPolicy,
+	result: Vec<VerifyResult>,
+) -> Result<Vec<VerifyResult>> {
+	// pin every query of this batch to the same finalized block,
+	// so the verdicts can be reproduced by others
+	let at = finalized_head(client).await.at_block(result.first().and_then(|r| r.number))?;
+	log::info!(
+		target: KILT_LOG_TARGET,
+		"query attestations at kilt finalized block {:}",
+		hex::encode(at)
+	);
+
+	// serve repeated root hashes from the cache, query the rest of the batch in one round trip
+	let mut cached = HashMap::new();
+	let mut root_hashes = vec![];
+	let valid_root_hashes =
+		result.iter().filter(|i| i.verdict.is_valid()).map(|i| Hash::from(i.root_hash));
+	for root_hash in valid_root_hashes {
+		match cache.get(&root_hash) {
+			Some(entry) => {
+				cached.insert(root_hash, entry);
+			},
+			None => root_hashes.push(root_hash),
+		}
+	}
+	let attestations = query_attestations(client, &root_hashes, Some(at))
+		.await
+		.at_block(result.first().and_then(|r| r.number))?;
+	for (root_hash, maybe_attest) in attestations.iter() {
+		if let Ok(maybe_attest) = maybe_attest {
+			cache.insert(*root_hash, maybe_attest.clone(), at);
+		}
+	}
 
-pub async fn filter(client: &KiltClient, result: Vec<VerifyResult>) -> Result<Vec<VerifyResult>> {
 	let mut v = vec![];
-	for i in result {
-		// query attestation details from kilt
-		let maybe_attest = query_attestation(client, i.root_hash.into())
-			.await
-			.map_err(|e| (i.number, e.into()))?;
+	for mut i in result {
+		// failed proofs stay failed whatever the credential is
+		if !i.verdict.is_valid() {
+			log::info!(
+				target: KILT_LOG_TARGET,
+				"skip attestation query for roothash: {:} | in block #{:?} | verdict: {:?}",
+				hex::encode(i.root_hash),
+				i.number,
+				i.verdict
+			);
+			v.push(i);
+			continue
+		}
+
+		let maybe_attest = if let Some(entry) = cached.get(&Hash::from(i.root_hash)) {
+			log::info!(
+				target: KILT_LOG_TARGET,
+				"attestation cache hit|root_hash:{:}|read at kilt block:{:}",
+				hex::encode(i.root_hash),
+				hex::encode(entry.at)
+			);
+			i.kilt_block_hash = Some(entry.at.into());
+			entry.attestation.clone()
+		} else {
+			i.kilt_block_hash = Some(at.into());
+			match attestations.get(&Hash::from(i.root_hash)) {
+				Some(Ok(maybe_attest)) => maybe_attest.clone(),
+				Some(Err(e)) => {
+					log::error!(
+						target: KILT_LOG_TARGET,
+						"fail to read attestation|root_hash:{:}|number:{:?}|err:{:?}",
+						hex::encode(i.root_hash),
+						i.number,
+						e
+					);
+					i.verdict = Verdict::AttestationUnavailable;
+					v.push(i);
+					continue
+				},
+				// every root hash of the batch is answered by `query_attestations`
+				None =>
+					return Err(Error::StorageValueMissing(hex::encode(i.root_hash)))
+						.at_block(i.number)
+						.for_request(i.request_hash),
+			}
+		};
 
-		let mut v_update = i.clone();
+		i.check_attestation(&maybe_attest.as_ref().into());
 
-		// submit to moonbeam if and only if
-		// the attestation neither empty or revoked
-		if maybe_attest.is_some() &&
-			Ok(()) == v_update.update_from_attestation(maybe_attest.unwrap())
-		{
+		// the event matches the attestation, then check whom it comes from
+		if let Some(attest) = maybe_attest.as_ref().filter(|_| i.verdict.is_valid()) {
+			let (verdict, chain) = resolve_trust(client, trust_policy, attest, Some(at))
+				.await
+				.at_block(i.number)
+				.for_request(i.request_hash)?;
+			i.verdict = verdict;
 			log::info!(
 				target: KILT_LOG_TARGET,
-				"roothash: {:} | in block #{:?} has been attested",
+				"roothash: {:} | in block #{:?} | trust chain: {:} | verdict: {:?}",
 				hex::encode(i.root_hash),
-				i.number
+				i.number,
+				chain,
+				i.verdict
+			);
+		}
+
+		// a deleted attester DID can no longer vouch for the credential
+		if let Some(attest) = maybe_attest.as_ref().filter(|_| i.verdict.is_valid()) {
+			let identity = resolve_did(client, &attest.attester, Some(at))
+				.await
+				.at_block(i.number)
+				.for_request(i.request_hash)?;
+			if !identity.is_active() {
+				i.verdict = Verdict::AttesterDidInactive;
+			}
+			log::info!(
+				target: KILT_LOG_TARGET,
+				"roothash: {:} | in block #{:?} | attester: {:}",
+				hex::encode(i.root_hash),
+				i.number,
+				identity
+			);
+			i.attester_did = Some(identity.did);
+			i.attester_web3_name = identity.web3_name;
+		}
+
+		if i.verdict.is_valid() {
+			log::info!(
+				target: KILT_LOG_TARGET,
+				"roothash: {:} | in block #{:?} has been attested by {:?} at kilt block {:}",
+				hex::encode(i.root_hash),
+				i.number,
+				i.attester_did,
+				hex::encode(at)
 			);
-			v.push(v_update)
 		} else {
 			log::warn!(
-                target: KILT_LOG_TARGET,
-                "attestaion is not valid for this root_hash|root_hash:{:}|data owner:{:}|number:{:?}",
-				hex::encode(i.root_hash), hex::encode(i.data_owner.0),
-				i.number
+				target: KILT_LOG_TARGET,
+				"attestaion is not valid for this root_hash|root_hash:{:}|data owner:{:}|number:{:?}|verdict:{:?}|claimed ctype:{:}|claimed attester:{:}|attested ctype:{:?}|attested attester:{:?}|attester did:{:?}",
+				hex::encode(i.root_hash),
+				hex::encode(i.data_owner.0),
+				i.number,
+				i.verdict,
+				hex::encode(i.c_type),
+				hex::encode(i.attester),
+				maybe_attest.as_ref().map(|a| a.ctype_hash),
+				maybe_attest.as_ref().map(|a| a.attester.clone()),
+				i.attester_did,
 			);
-			// TODO: notice the server that user's credential is not valid on kilt
 		}
+		v.push(i)
+	}
+	Ok(v)
+}
+
+// ask the aggregator whether the ctype and attester are the ones
+// the request was registered with
+pub async fn confirm_on_chain(
+	contract: &Contract<Http>,
+	result: Vec<VerifyResult>,
+) -> Result<Vec<VerifyResult>> {
+	let mut v = vec![];
+	for mut i in result {
+		if i.verdict.is_valid() {
+			let confirmed: bool = contract
+				.query(
+					CHECK_ATTESTATION,
+					(i.request_hash, i.c_type, i.attester),
+					None,
+					Web3Options::default(),
+					None,
+				)
+				.await
+				.map_err(MoonbeamError::from)
+				.at_block(i.number)
+				.for_request(i.request_hash)?;
+
+			if !confirmed {
+				log::warn!(
+					target: KILT_LOG_TARGET,
+					"aggregator rejects the ctype and attester of request|request_hash:{:}|ctype:{:}|attester:{:}|number:{:?}",
+					hex::encode(i.request_hash),
+					hex::encode(i.c_type),
+					hex::encode(i.attester),
+					i.number
+				);
+				i.verdict = Verdict::CTypeMismatch;
+			}
+		}
+		v.push(i)
 	}
 	Ok(v)
 }
 
 /// query attestation info from kilt network
-/// TODO: handle kilt error??
+/// at the given block, or the best block if `None`
 pub async fn query_attestation(
 	client: &KiltClient,
 	root_hash: Hash,
+	at: Option<Hash>,
 ) -> std::result::Result<Option<Attestation>, Error> {
 	let storage_key = get_attestation_storage_key::<Hash>(root_hash);
-	let mut times = 0;
-	let maybe_attestation_details = loop {
-		// connect to kilt and query attestation storage
-		match client.request_storage(&storage_key, None).await {
-			Ok(details) => break details,
-			Err(e) => {
-				match e {
-					RpcError::RequestTimeout | RpcError::Transport(_) => {
-						if times < KILT_MAX_RETRY_TIMES {
-							times += 1;
-							log::warn!(
-								target: KILT_LOG_TARGET,
-								"query kilt storage timeout, retry {:}/{:}",
-								times,
-								KILT_MAX_RETRY_TIMES
-							);
-							continue
-						}
-					},
-
-					_ => {},
-				}
-				return Err(e)?
-			},
-		}
-	};
-
-	// decode fetched storage data
-	let maybe_attestation: Option<Attestation> = match maybe_attestation_details {
-		Some(data) => Some(Decode::decode(&mut data.0.as_slice())?),
+	let maybe_attestation = match fetch_raw_storage(client, &storage_key, at).await? {
+		Some(data) => Some(client.decode_attestation(&data)?),
 		None => None,
 	};
 
 	log::info!(
 		target: KILT_LOG_TARGET,
-		"Kilt query result of roothash: [{:}] is {:?}",
+		"Kilt query result of roothash: [{:}] at block {:?} is {:?}",
 		hex::encode(root_hash),
+		at,
 		maybe_attestation
 	);
 
 	Ok(maybe_attestation)
 }
 
+/// query attestations of many root hashes at the same block in one round trip,
+/// one key failing to be decoded does not fail the others, while a key the node
+/// fails to answer fails the query
+pub async fn query_attestations(
+	client: &KiltClient,
+	root_hashes: &[Hash],
+	at: Option<Hash>,
+) -> std::result::Result<HashMap<Hash, std::result::Result<Option<Attestation>, Error>>, Error> {
+	let mut unique_hashes = root_hashes.to_vec();
+	unique_hashes.sort();
+	unique_hashes.dedup();
+	if unique_hashes.is_empty() {
+		return Ok(HashMap::new())
+	}
+
+	let keys = unique_hashes
+		.iter()
+		.map(|h| get_attestation_storage_key::<Hash>(*h))
+		.collect::<Vec<_>>();
+	let values = match fetch_storages(client, &keys, at).await {
+		Ok(values) => values,
+		// e.g. rpc method unavailable, query key by key instead
+		Err(e) => {
+			log::warn!(
+				target: KILT_LOG_TARGET,
+				"batch query of {:} attestations fails, query one by one, err: {:?}",
+				keys.len(),
+				e
+			);
+			let mut values = vec![];
+			for key in keys.iter() {
+				values.push(fetch_raw_storage(client, key, at).await);
+			}
+			values
+		},
+	};
+
+	let mut answered = vec![];
+	for (key, value) in keys.iter().zip(values) {
+		let value = match value {
+			// left out of the batch response, ask for the key alone
+			Err(Error::StorageValueMissing(_)) => fetch_raw_storage(client, key, at).await,
+			v => v,
+		};
+		match value {
+			// judging without the attestation would be final, let the batch be redelivered
+			Err(e) if e.is_retryable() => return Err(e),
+			v => answered.push(v),
+		}
+	}
+
+	let attestations = unique_hashes
+		.into_iter()
+		.zip(answered)
+		.map(|(root_hash, maybe_data)| {
+			let maybe_attestation = maybe_data.and_then(|maybe_data| match maybe_data {
+				Some(data) => Ok(Some(client.decode_attestation(&data)?)),
+				None => Ok(None),
+			});
+			log::info!(
+				target: KILT_LOG_TARGET,
+				"Kilt query result of roothash: [{:}] at block {:?} is {:?}",
+				hex::encode(root_hash),
+				at,
+				maybe_attestation
+			);
+			(root_hash, maybe_attestation)
+		})
+		.collect();
+	Ok(attestations)
+}
+
+/// fetch raw storage values of many keys in one request, retry on timeout.
+/// values are returned in the order of keys
+pub async fn fetch_storages(
+	client: &KiltClient,
+	storage_keys: &[StorageKey],
+	at: Option<Hash>,
+) -> std::result::Result<Vec<std::result::Result<Option<StorageData>, Error>>, Error> {
+	let query = move || client.raw_storages(storage_keys, at);
+	let res = client.retry.retry(KILT_LOG_TARGET, "batch query kilt storage", query);
+	client.metrics.observe_request(KILT_SERVICE, res).await
+}
+
+/// resolve the DID of an attester and its web3 name, retry on timeout
+pub async fn resolve_did(
+	client: &KiltClient,
+	identifier: &AccountId,
+	at: Option<Hash>,
+) -> std::result::Result<DidIdentity, Error> {
+	let values = fetch_storages(client, &did_storage_keys(identifier), at).await?;
+	DidIdentity::from_storages(identifier, values)
+}
+
+/// latest finalized block hash of kilt, retry on timeout
+pub async fn finalized_head(client: &KiltClient) -> std::result::Result<Hash, Error> {
+	let query = move || client.finalized_head();
+	let res = client.retry.retry(KILT_LOG_TARGET, "query kilt finalized head", query);
+	Ok(client.metrics.observe_request(KILT_SERVICE, res).await?)
+}
+
+/// fetch a storage value from kilt and decode it, retry on timeout
+pub async fn fetch_storage<T: Decode>(
+	client: &KiltClient,
+	storage_key: &StorageKey,
+	at: Option<Hash>,
+) -> std::result::Result<Option<T>, Error> {
+	let maybe_data = fetch_raw_storage(client, storage_key, at).await?;
+
+	// decode fetched storage data
+	match maybe_data {
+		Some(data) => Ok(Some(Decode::decode(&mut data.0.as_slice())?)),
+		None => Ok(None),
+	}
+}
+
+/// fetch a raw storage value from kilt, retry on timeout
+pub async fn fetch_raw_storage(
+	client: &KiltClient,
+	storage_key: &StorageKey,
+	at: Option<Hash>,
+) -> std::result::Result<Option<StorageData>, Error> {
+	// connect to kilt and query storage, through a storage proof if a checkpoint is set
+	let query = move || client.raw_storage(storage_key, at);
+	let res = client.retry.retry(KILT_LOG_TARGET, "query kilt storage", query);
+	client.metrics.observe_request(KILT_SERVICE, res).await
+}
+
 #[cfg(test)]
 mod tests {
 	use std::str::FromStr;
@@ -133,13 +403,13 @@ mod tests {
 		let right_root_hash =
 			Hash::from_str("af6e8c774b0f7409743f7e28e29fd3196d0eee72c66e57c550302abea4336933")
 				.expect("root hash from string error");
-		let maybe_right_attest = query_attestation(&kilt_client, right_root_hash).await;
+		let maybe_right_attest = query_attestation(&kilt_client, right_root_hash, None).await;
 		assert_eq!(maybe_right_attest.unwrap().unwrap(), attest_exp);
 
 		let empty_root_hash =
 			Hash::from_str("7b6e8c774b0f7409743f7e28e29fd3196d0eee72c66e57c550302abea4336966")
 				.expect("root hash from string error");
-		let maybe_empty_attest = query_attestation(&kilt_client, empty_root_hash).await;
+		let maybe_empty_attest = query_attestation(&kilt_client, empty_root_hash, None).await;
 		assert_eq!(maybe_empty_attest.unwrap(), None);
 	}
 
@@ -152,7 +422,7 @@ mod tests {
 		let empty_root_hash =
 			Hash::from_str("7b6e8c774b0f7409743f7e28e29fd3196d0eee72c66e57c550302abea4336966")
 				.expect("root hash from string error");
-		let maybe_empty_attest = query_attestation(&kilt_client, empty_root_hash).await;
+		let maybe_empty_attest = query_attestation(&kilt_client, empty_root_hash, None).await;
 		assert_eq!(maybe_empty_attest.unwrap(), None);
 	}
 }
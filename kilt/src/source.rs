@@ -0,0 +1,46 @@
+use async_trait::async_trait;
+
+use keeper_primitives::{
+	attestation::{AttestationSource, AttestationStatus},
+	kilt::{AttestationCache, KiltClient, TrustPolicy},
+	Bytes32, Error, Hash, Result as KeeperResult, VerifyResult,
+};
+
+use super::{filter, finalized_head, query_attestation};
+
+/// attestations on kilt, read at the finalized head through the shared cache
+#[derive(Clone, Debug)]
+pub struct KiltAttestationSource {
+	client: KiltClient,
+	cache: AttestationCache,
+	trust_policy: TrustPolicy,
+}
+
+impl KiltAttestationSource {
+	pub fn new(client: KiltClient, cache: AttestationCache, trust_policy: TrustPolicy) -> Self {
+		KiltAttestationSource { client, cache, trust_policy }
+	}
+}
+
+#[async_trait]
+impl AttestationSource for KiltAttestationSource {
+	fn name(&self) -> &'static str {
+		"kilt"
+	}
+
+	async fn attestation_status(&self, root_hash: Bytes32) -> Result<AttestationStatus, Error> {
+		let root_hash = Hash::from(root_hash);
+		if let Some(entry) = self.cache.get(&root_hash) {
+			return Ok(entry.attestation.as_ref().into())
+		}
+		let at = finalized_head(&self.client).await?;
+		let maybe_attest = query_attestation(&self.client, root_hash, Some(at)).await?;
+		self.cache.insert(root_hash, maybe_attest.clone(), at);
+		Ok(maybe_attest.as_ref().into())
+	}
+
+	// the whole batch in one storage query, with the trust policy and attester DIDs
+	async fn check(&self, result: Vec<VerifyResult>) -> KeeperResult<Vec<VerifyResult>> {
+		filter(&self.client, &self.cache, &self.trust_policy, result).await
+	}
+}
@@ -1,32 +1,35 @@
-use std::time::Duration;
+use async_trait::async_trait;
 
 use keeper_primitives::{
-	ConfigInstance, Delay, Error, MqReceiver, MqSender, CHANNEL_LOG_TARGET, U64,
+	kilt::KILT_LOG_TARGET, pipeline, ConfigInstance, Result, Stage, VerifyResult,
 };
 
-pub async fn task_attestation(
-	config: &ConfigInstance,
-	msg_queue: (&mut MqSender, &mut MqReceiver),
-) -> std::result::Result<(), (Option<U64>, Error)> {
-	while let Ok(r) = msg_queue.1.recv_timeout(Delay::new(Duration::from_secs(1))).await {
-		// while let Ok(events) = event_receiver.recv().await {
-		let r = match r {
-			Some(a) => a,
-			None => continue,
-		};
-		log::info!(target: CHANNEL_LOG_TARGET, "recv msg in task3");
-		// parse verify result from str to VerifyResult
-		let inputs = serde_json::from_slice(&*r).map_err(|e| (None, e.into()))?;
+/// check the attestation behind every verified proof
+#[derive(Clone, Copy, Debug, Default)]
+pub struct AttestStage;
 
-		// have handled resoluble error inside filter
-		let res = super::filter(&config.kilt_client, inputs).await.map_err(|e| (e.0, e.1))?;
+#[async_trait]
+impl pipeline::Stage for AttestStage {
+	type Input = VerifyResult;
+	type Output = VerifyResult;
 
-		if !res.is_empty() {
-			let message_to_send = serde_json::to_vec(&res);
-			msg_queue.0.send(message_to_send.unwrap()).await.map_err(|e| (None, e.into()))?;
-		}
-		r.commit().map_err(|e| (None, e.into()))?;
+	const KIND: Stage = Stage::Kilt;
+	const LOG_TARGET: &'static str = KILT_LOG_TARGET;
+
+	fn endpoint<'a>(&self, config: &'a ConfigInstance) -> &'a str {
+		&config.kilt_client.ip_address
 	}
 
-	Ok(())
+	async fn process(
+		&self,
+		config: &ConfigInstance,
+		inputs: Vec<VerifyResult>,
+	) -> Result<Vec<VerifyResult>> {
+		// have handled resoluble error inside the source
+		let mut res = config.attestation_source.check(inputs).await?;
+		if config.check_attestation_on_chain {
+			res = super::confirm_on_chain(&config.aggregator_contract, res).await?;
+		}
+		Ok(res)
+	}
 }
@@ -0,0 +1,110 @@
+use std::fmt;
+
+use keeper_primitives::{
+	kilt::{
+		get_delegation_node_storage_key, AccountId, Attestation, DelegationNode, Error, KiltClient,
+		TrustPolicy, KILT_LOG_TARGET,
+	},
+	Hash, Verdict,
+};
+
+use super::fetch_storage;
+
+// deepest delegation hierarchy the keeper walks through
+const MAX_DELEGATION_DEPTH: usize = 32;
+
+/// how an attestation links to the attester the keeper trusts
+#[derive(Clone, Debug, PartialEq)]
+pub enum TrustChain {
+	/// attested by the attester itself
+	Direct(AccountId),
+	/// attested through delegation nodes, from the attester's node up to the hierarchy root
+	Delegated(Vec<(Hash, AccountId)>),
+}
+
+impl fmt::Display for TrustChain {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			TrustChain::Direct(attester) => write!(f, "attester {}", attester),
+			TrustChain::Delegated(nodes) => {
+				let path = nodes
+					.iter()
+					.map(|(id, owner)| format!("{}({})", hex::encode(id), owner))
+					.collect::<Vec<_>>()
+					.join(" -> ");
+				write!(f, "delegation {}", path)
+			},
+		}
+	}
+}
+
+/// resolve where the attestation comes from and check it against the trust policy,
+/// delegation nodes are read at the same block as the attestation
+pub async fn resolve_trust(
+	client: &KiltClient,
+	policy: &TrustPolicy,
+	attest: &Attestation,
+	at: Option<Hash>,
+) -> std::result::Result<(Verdict, TrustChain), Error> {
+	let delegation_id = match attest.delegation_id {
+		Some(id) => id,
+		None => {
+			let verdict = if policy.is_attester_trusted(&attest.ctype_hash, &attest.attester) {
+				Verdict::Valid
+			} else {
+				Verdict::AttesterUntrusted
+			};
+			return Ok((verdict, TrustChain::Direct(attest.attester.clone())))
+		},
+	};
+
+	if !policy.accept_delegations {
+		return Ok((Verdict::AttesterUntrusted, TrustChain::Delegated(vec![])))
+	}
+
+	let mut nodes = vec![];
+	let mut root = None;
+	let mut current = Some(delegation_id);
+	while let Some(id) = current {
+		if nodes.len() >= MAX_DELEGATION_DEPTH {
+			log::warn!(
+				target: KILT_LOG_TARGET,
+				"delegation hierarchy of node {:} is deeper than {:}",
+				hex::encode(delegation_id),
+				MAX_DELEGATION_DEPTH
+			);
+			return Ok((Verdict::AttesterUntrusted, TrustChain::Delegated(nodes)))
+		}
+
+		let maybe_node: Option<DelegationNode> =
+			fetch_storage(client, &get_delegation_node_storage_key(id), at).await?;
+		let node = match maybe_node {
+			Some(n) => n,
+			None => {
+				log::warn!(
+					target: KILT_LOG_TARGET,
+					"delegation node {:} is not found on kilt",
+					hex::encode(id)
+				);
+				return Ok((Verdict::AttesterUntrusted, TrustChain::Delegated(nodes)))
+			},
+		};
+
+		nodes.push((id, node.details.owner.clone()));
+		// revoking any node of the chain revokes everything attested below it
+		if node.details.revoked {
+			return Ok((Verdict::AttestationRevoked, TrustChain::Delegated(nodes)))
+		}
+		root = Some(node.hierarchy_root_id);
+		current = node.parent;
+	}
+
+	// the walk ends at the root node, its owner created the hierarchy
+	let trusted = match (root, nodes.last()) {
+		(Some(root), Some((_, root_owner))) =>
+			policy.is_delegation_trusted(&attest.ctype_hash, &attest.attester, &root, root_owner),
+		_ => false,
+	};
+	let verdict = if trusted { Verdict::Valid } else { Verdict::AttesterUntrusted };
+	Ok((verdict, TrustChain::Delegated(nodes)))
+}
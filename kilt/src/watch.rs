@@ -0,0 +1,150 @@
+use std::{
+	collections::{BTreeSet, HashMap},
+	time::{SystemTime, UNIX_EPOCH},
+};
+
+use tokio::time::{timeout_at, Duration, Instant};
+
+use keeper_primitives::{
+	kilt::{get_attestation_storage_key, Error as KiltError, KiltSubscriber, KILT_LOG_TARGET},
+	monitor::{MonitorMetrics, MonitorSender},
+	Bytes32, ConfigInstance, Hash, KeeperError, Result, RevocationRecord, StorageChangeSet,
+	StorageKey,
+};
+
+// re-subscribe with newly approved or cached attestations after this interval. a new
+// subscription is sent the current values first, so a revocation in between is not missed
+const WATCH_REFRESH_SECS: u64 = 60;
+
+// watch attestations behind submitted passing verdicts and those the attestation cache may
+// serve, drop revoked ones from the cache and alert on revoked approvals. every run connects
+// to `ws_url` again, a dropped connection ends the run
+pub async fn task_revocation_watch(
+	config: &ConfigInstance,
+	ws_url: &str,
+	monitor_sender: MonitorSender,
+) -> Result<()> {
+	let subscriber = KiltSubscriber::try_from_url(ws_url).await?;
+	loop {
+		let root_hashes = watched_root_hashes(config)?;
+		let mut deadline = Instant::now() + Duration::from_secs(WATCH_REFRESH_SECS);
+		if root_hashes.is_empty() {
+			tokio::time::sleep_until(deadline).await;
+			continue
+		}
+
+		let keys: HashMap<StorageKey, Bytes32> = root_hashes
+			.iter()
+			.map(|r| (get_attestation_storage_key(Hash::from(*r)), *r))
+			.collect();
+		let storage_keys = keys.keys().cloned().collect::<Vec<_>>();
+		let mut subscription = subscriber.subscribe_storage(&storage_keys).await?;
+		log::info!(
+			target: KILT_LOG_TARGET,
+			"watching {} attestations for revocation",
+			keys.len()
+		);
+
+		loop {
+			match timeout_at(deadline, subscription.next()).await {
+				Ok(Ok(Some(change_set))) =>
+					handle_changes(config, &subscriber, &keys, change_set, &monitor_sender).await?,
+				Ok(Ok(None)) => return Err(KiltError::SubscriptionClosed.into()),
+				Ok(Err(e)) => return Err(KiltError::KiltClientError(e).into()),
+				Err(_) => {
+					let latest = watched_root_hashes(config)?;
+					if latest == root_hashes {
+						deadline = Instant::now() + Duration::from_secs(WATCH_REFRESH_SECS);
+						continue
+					}
+					// the set changed, subscribe again
+					break
+				},
+			}
+		}
+	}
+}
+
+// attestations approved by this process, and those its cache may still serve
+fn watched_root_hashes(config: &ConfigInstance) -> Result<BTreeSet<Bytes32>> {
+	let mut root_hashes = config.database.approved_root_hashes()?;
+	root_hashes.extend(config.attestation_cache.root_hashes().into_iter().map(Bytes32::from));
+	Ok(root_hashes)
+}
+
+async fn handle_changes(
+	config: &ConfigInstance,
+	subscriber: &KiltSubscriber,
+	keys: &HashMap<StorageKey, Bytes32>,
+	change_set: StorageChangeSet<Hash>,
+	monitor_sender: &MonitorSender,
+) -> Result<()> {
+	let mut revoked = BTreeSet::new();
+	for (key, maybe_data) in change_set.changes {
+		let root_hash = match keys.get(&key) {
+			Some(r) => *r,
+			None => continue,
+		};
+		let is_revoked = match maybe_data {
+			// removed attestation can no longer back the verdict
+			None => true,
+			Some(data) => match config.kilt_client.decode_attestation(&data) {
+				Ok(attest) => attest.revoked,
+				Err(e) => {
+					log::warn!(
+						target: KILT_LOG_TARGET,
+						"fail to decode watched attestation|root_hash:{:}|err:{:?}",
+						hex::encode(root_hash),
+						e
+					);
+					false
+				},
+			},
+		};
+		if is_revoked {
+			revoked.insert(root_hash);
+		}
+	}
+
+	for root_hash in revoked {
+		config.attestation_cache.invalidate(&Hash::from(root_hash));
+		let approved = config.database.approved(&root_hash)?;
+		// already handled by an earlier notification
+		if approved.is_empty() {
+			continue
+		}
+		let record = RevocationRecord {
+			root_hash,
+			kilt_block_hash: change_set.block.into(),
+			observed_at: SystemTime::now()
+				.duration_since(UNIX_EPOCH)
+				.map(|d| d.as_secs())
+				.unwrap_or_default(),
+			request_hashes: approved.iter().map(|v| v.request_hash).collect(),
+		};
+		// drops the approved requests along, so they are only gone once recorded
+		config.database.insert_revocation(&record)?;
+
+		let number = approved.iter().filter_map(|v| v.number).max();
+		log::warn!(
+			target: KILT_LOG_TARGET,
+			"attestation revoked after approval|root_hash:{:}|kilt block:{:}|requests:{:?}",
+			hex::encode(root_hash),
+			hex::encode(record.kilt_block_hash),
+			record.request_hashes.iter().map(hex::encode).collect::<Vec<_>>()
+		);
+		if cfg!(feature = "monitor") {
+			let error = KeeperError::from(KiltError::RevokedAfterApproval(hex::encode(root_hash)))
+				.at_block(number);
+			let monitor_metrics = MonitorMetrics::new(
+				KILT_LOG_TARGET.to_string(),
+				&error,
+				config.keeper_address,
+				&subscriber.ip_address,
+			);
+			let _ = monitor_sender.send(monitor_metrics).await;
+		}
+	}
+
+	Ok(())
+}
@@ -1,17 +1,21 @@
 use secp256k1::SecretKey;
 
 use keeper_primitives::{
+	metrics::Metrics,
 	moonbeam::{
 		self, utils::query_submit_and_finish_result, Events, ProofEvent, IS_FINISHED,
 		MOONBEAM_LISTENED_EVENT, MOONBEAM_SCAN_LOG_TARGET, MOONBEAM_SCAN_SPAN,
 		MOONBEAM_SUBMIT_LOG_TARGET, MOONBEAM_TRANSACTION_CONFIRMATIONS, SUBMIT_STATUS_QUERY,
-		SUBMIT_TX_MAX_RETRY_TIMES, SUBMIT_VERIFICATION,
+		SUBMIT_VERIFICATION,
 	},
-	Address, Contract, Http, MoonbeamClient, Result as KeeperResult, VerifyResult, Web3Options,
-	U64,
+	verdict::{Decision, VERDICT_LOG_TARGET},
+	Address, Contract, Database, ErrorContext, Http, MoonbeamClient, Result as KeeperResult,
+	RetryPolicy, VerdictPolicy, VerifyResult, Web3Options, U64,
 };
-pub use task::{task_scan, task_submit};
+pub use source::EvmAttestationSource;
+pub use task::{task_scan, update_keeper_balance, SubmitStage};
 
+mod source;
 mod task;
 
 // scan moonbeam events
@@ -42,14 +46,16 @@ pub async fn scan_events(
 		best
 	);
 	// parse event
-	let r = moonbeam::utils::events::<_, ProofEvent>(
-		client.eth(),
-		proof_contract,
-		MOONBEAM_LISTENED_EVENT,
-		Some(start),
-		Some(end),
-	)
-	.await;
+	let query = move || {
+		moonbeam::utils::events::<_, ProofEvent>(
+			client.eth(),
+			proof_contract,
+			MOONBEAM_LISTENED_EVENT,
+			Some(start),
+			Some(end),
+		)
+	};
+	let r = client.retry.retry(MOONBEAM_SCAN_LOG_TARGET, "query proof events", query).await;
 
 	// if event parse error, return Err(start) and output error log
 	let res = match r {
@@ -60,7 +66,7 @@ pub async fn scan_events(
 				"Moonbeam Scan Err: Event parse error. {:?}",
 				err
 			);
-			return Err((Some(start), err.into()))
+			return Err(err).at_block(Some(start))
 		},
 	};
 
@@ -86,8 +92,8 @@ pub async fn scan_events(
 				number
 			);
 
-			// complete proof event
-			proof_event.set_block_number(number);
+			// complete proof event, the log coordinates identify the request downstream
+			proof_event.set_log(&log);
 
 			result.push(proof_event.clone());
 			log::info!(
@@ -108,12 +114,57 @@ pub async fn scan_events(
 	}
 }
 
+// record every verdict locally, and keep those the policy allows to submit
+pub fn apply_policy(
+	policy: &VerdictPolicy,
+	db: &Database,
+	res: Vec<VerifyResult>,
+) -> KeeperResult<Vec<VerifyResult>> {
+	let mut to_submit = vec![];
+	for v in res {
+		db.insert_verdict(&v).at_block(v.number).for_request(v.request_hash)?;
+		match policy.decide(v.verdict) {
+			Decision::Withhold => {
+				log::info!(
+					target: VERDICT_LOG_TARGET,
+					"withhold verdict {:?} | data owner: {:} | request_hash: {:} | number: {:?}",
+					v.verdict,
+					v.data_owner,
+					hex::encode(v.request_hash),
+					v.number
+				);
+			},
+			Decision::SubmitPassed | Decision::SubmitFailed => to_submit.push(v),
+		}
+	}
+	Ok(to_submit)
+}
+
+// passing verdicts on chain are watched for attestation revocation
+fn record_approved(db: &Database, v: &VerifyResult) {
+	if !v.is_passed() {
+		return
+	}
+	if let Err(e) = db.insert_approved(v) {
+		log::warn!(
+			target: MOONBEAM_SUBMIT_LOG_TARGET,
+			"Fail to record approved request_hash: {:}, err: {:?}",
+			hex::encode(v.request_hash),
+			e
+		);
+	}
+}
+
 pub async fn submit_txs(
 	contract: &Contract<Http>,
 	keeper_pri: SecretKey,
 	keeper_address: Address,
+	db: &Database,
+	retry: &RetryPolicy,
+	metrics: &Metrics,
 	res: Vec<VerifyResult>,
-) -> std::result::Result<(), (Option<U64>, keeper_primitives::moonbeam::Error)> {
+) -> KeeperResult<usize> {
+	let mut submitted = 0;
 	for v in res {
 		let query_submit_and_finish_results = query_submit_and_finish_result(
 			contract,
@@ -122,7 +173,7 @@ pub async fn submit_txs(
 			IS_FINISHED,
 			(v.data_owner, v.request_hash),
 			v.request_hash,
-			SUBMIT_TX_MAX_RETRY_TIMES,
+			retry,
 		)
 		.await;
 
@@ -136,16 +187,22 @@ pub async fn submit_txs(
 					is_finished,
 				);
 
-				if !has_submitted && !is_finished {
+				if has_submitted {
+					// submitted before a restart, approval may not be recorded yet
+					record_approved(db, &v);
+				} else if !is_finished {
 					log::info!(
 						target: MOONBEAM_SUBMIT_LOG_TARGET,
-						"Start submitting: tx which contains user address: {:} |request_hash: {:}| root hash : {:} | isPassed: {}",
+						"Start submitting: tx which contains user address: {:} |request_hash: {:}| root hash : {:} | isPassed: {} | verdict: {:?}",
 						v.data_owner,
 						hex::encode(v.request_hash),
 						hex::encode(v.root_hash),
-						v.is_passed
+						v.is_passed(),
+						v.verdict
 					);
 
+					// not retried, a lost transaction may still be mined, `hasSubmitted` is
+					// checked again when the message is redelivered
 					let r = contract
 						.signed_call_with_confirmations(
 							SUBMIT_VERIFICATION,
@@ -154,9 +211,9 @@ pub async fn submit_txs(
 								v.request_hash,
 								v.c_type,
 								v.root_hash,
-								v.is_passed,
+								v.is_passed(),
 								v.attester,
-								v.calc_output,
+								v.calc_output.clone(),
 							),
 							{
 								// todo: auto adjust options here
@@ -172,32 +229,37 @@ pub async fn submit_txs(
 					match r {
 						Ok(r) => {
 							log::info!(
-						target: MOONBEAM_SUBMIT_LOG_TARGET,
-						"Successfully submit verification|tx:{:}|data owner:{:}|root_hash:{:}|is_passed: {:}|attester: {:}",
-						r.transaction_hash,
-						v.data_owner,
-						hex::encode(v.root_hash),
-						v.is_passed,
-						hex::encode(v.attester),
-					)
+								target: MOONBEAM_SUBMIT_LOG_TARGET,
+								"Successfully submit verification|tx:{:}|data owner:{:}|root_hash:{:}|is_passed: {:}|attester: {:}",
+								r.transaction_hash,
+								v.data_owner,
+								hex::encode(v.root_hash),
+								v.is_passed(),
+								hex::encode(v.attester),
+							);
+							metrics.observe_submission(r.gas_used);
+							record_approved(db, &v);
+							submitted += 1;
 						},
 						Err(e) => {
 							log::error!(
-						target: MOONBEAM_SUBMIT_LOG_TARGET,
-						"Error submit verification |data owner:{:}|root_hash:{:}|request_hash: {:}, err: {:?}",
-						v.data_owner,
-						hex::encode(v.root_hash),
-						hex::encode(v.request_hash),
-						e
-					);
-							return Err((v.number, e.into()))
+								target: MOONBEAM_SUBMIT_LOG_TARGET,
+								"Error submit verification |data owner:{:}|root_hash:{:}|request_hash: {:}, err: {:?}",
+								v.data_owner,
+								hex::encode(v.root_hash),
+								hex::encode(v.request_hash),
+								e
+							);
+							return Err(moonbeam::Error::from(e))
+								.at_block(v.number)
+								.for_request(v.request_hash)
 						},
 					}
 				}
 			},
-			Err(e) => return Err((v.number, e.into())),
+			Err(e) => return Err(e).at_block(v.number).for_request(v.request_hash),
 		}
 	}
 
-	Ok(())
+	Ok(submitted)
 }
@@ -0,0 +1,45 @@
+use async_trait::async_trait;
+
+use keeper_primitives::{
+	attestation::{AttestationRecord, AttestationSource, AttestationStatus},
+	moonbeam::{Error as MoonbeamError, GET_ATTESTATION, MOONBEAM_QUERY_LOG_TARGET},
+	Bytes32, Contract, Error, Http, RetryPolicy, Web3Options,
+};
+
+/// attestations kept by a registry contract on moonbeam
+#[derive(Clone, Debug)]
+pub struct EvmAttestationSource {
+	registry: Contract<Http>,
+	retry: RetryPolicy,
+}
+
+impl EvmAttestationSource {
+	pub fn new(registry: Contract<Http>, retry: RetryPolicy) -> Self {
+		EvmAttestationSource { registry, retry }
+	}
+}
+
+#[async_trait]
+impl AttestationSource for EvmAttestationSource {
+	fn name(&self) -> &'static str {
+		"evm"
+	}
+
+	async fn attestation_status(&self, root_hash: Bytes32) -> Result<AttestationStatus, Error> {
+		let registry = &self.registry;
+		let query = move || async move {
+			registry
+				.query(GET_ATTESTATION, (root_hash,), None, Web3Options::default(), None)
+				.await
+				.map_err(MoonbeamError::from)
+		};
+		let (ctype_hash, attester, revoked, exists): (Bytes32, Bytes32, bool, bool) = self
+			.retry
+			.retry(MOONBEAM_QUERY_LOG_TARGET, "query attestation registry", query)
+			.await?;
+		if !exists {
+			return Ok(AttestationStatus::Missing)
+		}
+		Ok(AttestationStatus::Attested(AttestationRecord { ctype_hash, attester, revoked }))
+	}
+}
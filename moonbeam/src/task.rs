@@ -1,40 +1,61 @@
+use async_trait::async_trait;
+
 use crate::U64;
 use keeper_primitives::{
-	monitor::{MonitorMetrics, MonitorSender},
-	moonbeam::{MOONBEAM_SCAN_LOG_TARGET, MOONBEAM_SUBMIT_LOG_TARGET},
-	ConfigInstance, Delay, Error, JsonParse, MqReceiver, MqSender, CHANNEL_LOG_TARGET,
+	monitor::MonitorSender,
+	moonbeam::{MOONBEAM_BLOCK_DURATION, MOONBEAM_SCAN_LOG_TARGET, MOONBEAM_SUBMIT_LOG_TARGET},
+	pipeline,
+	queue::Backpressure,
+	ConfigInstance, ErrorContext, MqSender, Shutdown, Stage, VerifyResult, CHANNEL_LOG_TARGET,
 };
 use tokio::time::{sleep, Duration};
 
 use super::KeeperResult;
 
+// label of scanned events in the stage metrics
+const SCAN_STAGE: &str = "scan";
+
+/// scan from the checkpoint in the database until a shutdown is requested, the checkpoint
+/// moves on after each range. paused while `backpressure` holds it
 pub async fn task_scan(
 	config: &ConfigInstance,
 	msg_sender: &mut MqSender,
-	mut start: U64,
+	backpressure: &mut Backpressure,
 	_monitor_sender: MonitorSender,
+	shutdown: &Shutdown,
 ) -> KeeperResult<()> {
-	let mut tmp_start_cache = 0.into();
+	let mut start = config.database.scan_checkpoint()?.unwrap_or_default();
+	let mut shutdown = shutdown.clone();
+	while !shutdown.is_requested() {
+		// downstream stages catch up before more events are sent
+		backpressure.wait(&mut shutdown).await.at_block(Some(start))?;
+		if shutdown.is_requested() {
+			break
+		}
 
-	loop {
 		let maybe_best = config.moonbeam_client.best_number().await;
 		let best = match maybe_best {
 			Ok(b) => b,
 			Err(e) => {
 				log::error!(
-						target: MOONBEAM_SCAN_LOG_TARGET,
-						"Fail to get latest block number in tasks moonbeam scan, after #{:?} scanned, err is {:?}",
-						start,
-						 e
-					);
-				return Err((None, e.into()))
+					target: MOONBEAM_SCAN_LOG_TARGET,
+					"Fail to get latest block number in tasks moonbeam scan, after #{:?} scanned, err is {:?}",
+					start,
+					 e
+				);
+				return Err(e.into())
 			},
 		};
 
-		// local network check
-		// only work if the chain is frozen
-		if (start == tmp_start_cache) && (start == best) {
-			// do nothing here
+		config.metrics.set_scan_progress(start, best);
+		// every block up to best has been scanned, wait for the next one
+		if start > best {
+			log::info!("sleep for scan block... current:{:}|best:{:}", start, best);
+			let block_duration = Duration::from_secs(MOONBEAM_BLOCK_DURATION);
+			tokio::select! {
+				_ = sleep(block_duration) => {},
+				_ = shutdown.requested() => {},
+			}
 			continue
 		}
 
@@ -44,78 +65,85 @@ pub async fn task_scan(
 			super::scan_events(start, best, &config.moonbeam_client, &config.proof_contract)
 				.await?;
 
-		if res.is_some() {
-			// send result to channel
-			// unwrap MUST succeed
-			let output = res.unwrap().into_bytes().map_err(|e| (Some(start), e.into()))?;
-
-			let status = msg_sender.send(output).await;
-			if let Err(e) = status {
-				log::error!(
-					target: CHANNEL_LOG_TARGET,
-					"Fail to write data in block from: #{:?} into event channel file",
-					start,
-				);
-				return Err((Some(start), e.into()))
-			}
-			// After the proofevent list successfully sent to task2
-			// reset the tmp_start_cache
-			tmp_start_cache = end;
-		} else {
-			let latest = &config.moonbeam_client.best_number().await.unwrap_or_default();
-			if start == *latest {
-				// if current start is the best number, then sleep the block duration.
-				log::info!("sleep for scan block... current:{:}|best:{:}", start, latest);
-				sleep(Duration::from_secs(keeper_primitives::moonbeam::MOONBEAM_BLOCK_DURATION))
-					.await;
+		if let Some(events) = res {
+			// logs may be seen again after a restart in the middle of a range
+			let events = config.database.unscanned(events).at_block(Some(start))?;
+			config.metrics.inc_stage_events(SCAN_STAGE, events.len());
+			// one request per message, so each is verified and committed on its own
+			for event in events {
+				let output = serde_json::to_vec(&event).at_block(Some(start))?;
+				let status = msg_sender.send(output).await;
+				if let Err(e) = status {
+					log::error!(
+						target: CHANNEL_LOG_TARGET,
+						"Fail to write data in block from: #{:?} into event channel file",
+						start,
+					);
+					return Err(e).at_block(Some(start))
+				}
+				config.database.mark_scanned(&event).at_block(Some(start))?;
 			}
 		}
 
-		// reset scan start point
-		start = end;
+		// `end` is inclusive, continue from the next block
+		start = end + U64::one();
+		config.database.set_scan_checkpoint(start).at_block(Some(start))?;
+		config.database.prune_scanned(start).at_block(Some(start))?;
 	}
+
+	log::info!(target: MOONBEAM_SCAN_LOG_TARGET, "scan stopped, continue from #{} next run", start);
+	Ok(())
 }
 
-pub async fn task_submit(
-	config: &ConfigInstance,
-	msg_receiver: &mut MqReceiver,
-	monitor_sender: MonitorSender,
-) -> std::result::Result<(), (Option<U64>, Error)> {
-	while let Ok(r) = msg_receiver.recv_timeout(Delay::new(Duration::from_secs(1))).await {
-		// while let Ok(events) = event_receiver.recv().await {
-		let r = match r {
-			Some(a) => a,
-			None => continue,
-		};
-		log::info!("recv msg in task4");
-		// in theory, inputs wont be empty here
-		let inputs = serde_json::from_slice(&*r).map_err(|e| (None, e.into()))?;
+/// submit verdicts on-chain with the keeper key
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SubmitStage;
+
+#[async_trait]
+impl pipeline::Stage for SubmitStage {
+	type Input = VerifyResult;
+	// last stage, nothing is sent on
+	type Output = ();
+
+	const KIND: Stage = Stage::Submit;
+	const LOG_TARGET: &'static str = MOONBEAM_SUBMIT_LOG_TARGET;
+
+	fn endpoint<'a>(&self, config: &'a ConfigInstance) -> &'a str {
+		&config.moonbeam_client.ip_address
+	}
 
-		let res = super::submit_txs(
+	async fn process(
+		&self,
+		config: &ConfigInstance,
+		inputs: Vec<VerifyResult>,
+	) -> KeeperResult<Vec<()>> {
+		let inputs = super::apply_policy(&config.policy, &config.database, inputs)?;
+		let submitted = super::submit_txs(
 			&config.aggregator_contract,
 			config.private_key,
 			config.keeper_address,
+			&config.database,
+			&config.moonbeam_client.retry,
+			&config.metrics,
 			inputs,
 		)
-		.await;
-
-		match res {
-			Ok(_) => {
-				r.commit().map_err(|e| (None, e.into()))?;
-			},
-			Err(e) =>
-				if cfg!(feature = "monitor") {
-					let monitor_metrics = MonitorMetrics::new(
-						MOONBEAM_SUBMIT_LOG_TARGET.to_string(),
-						e.0,
-						&e.1.into(),
-						config.keeper_address,
-						&config.moonbeam_client.ip_address,
-					);
-					monitor_sender.send(monitor_metrics).await;
-				},
+		.await?;
+		// only a submission spends from the keeper account
+		if submitted > 0 {
+			update_keeper_balance(config).await;
 		}
+		Ok(vec![])
 	}
+}
 
-	Ok(())
+/// read the keeper balance into the metrics, a failed read keeps the last value
+pub async fn update_keeper_balance(config: &ConfigInstance) {
+	match config.moonbeam_client.eth().balance(config.keeper_address, None).await {
+		Ok(balance) => config.metrics.set_keeper_balance(balance),
+		Err(e) => log::warn!(
+			target: MOONBEAM_SUBMIT_LOG_TARGET,
+			"fail to query keeper balance, err: {:?}",
+			e
+		),
+	}
 }
@@ -0,0 +1,103 @@
+use std::fmt;
+
+use async_trait::async_trait;
+
+use super::{kilt::Attestation, Bytes32, Deserialize, Error, Serialize, Verdict, VerifyResult};
+
+pub const ATTESTATION_LOG_TARGET: &str = "Attestation";
+
+/// where the keeper checks credentials, KILT unless configured otherwise
+#[derive(Eq, PartialEq, Clone, Debug, Deserialize, Serialize)]
+#[serde(tag = "source", rename_all = "snake_case")]
+pub enum AttestationSourceConfig {
+	Kilt,
+	// attestation registry contract on moonbeam
+	Evm { registry_contract: String },
+}
+
+impl Default for AttestationSourceConfig {
+	fn default() -> Self {
+		AttestationSourceConfig::Kilt
+	}
+}
+
+/// attestation of a credential root hash, normalized across sources
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AttestationRecord {
+	pub ctype_hash: Bytes32,
+	pub attester: Bytes32,
+	pub revoked: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AttestationStatus {
+	/// nothing attested for the root hash
+	Missing,
+	Attested(AttestationRecord),
+}
+
+impl AttestationStatus {
+	pub fn record(&self) -> Option<&AttestationRecord> {
+		match self {
+			AttestationStatus::Missing => None,
+			AttestationStatus::Attested(record) => Some(record),
+		}
+	}
+}
+
+impl From<Option<&Attestation>> for AttestationStatus {
+	fn from(maybe_attest: Option<&Attestation>) -> Self {
+		match maybe_attest {
+			Some(attest) => AttestationStatus::Attested(AttestationRecord {
+				ctype_hash: attest.ctype_hash.into(),
+				attester: attest.attester.clone().into(),
+				revoked: attest.revoked,
+			}),
+			None => AttestationStatus::Missing,
+		}
+	}
+}
+
+/// looks up the attestation behind a credential root hash
+#[async_trait]
+pub trait AttestationSource: fmt::Debug + Send + Sync {
+	/// short name shown in logs
+	fn name(&self) -> &'static str;
+
+	async fn attestation_status(&self, root_hash: Bytes32) -> Result<AttestationStatus, Error>;
+
+	/// check the credential behind every verified proof, the verdict is downgraded if the
+	/// attestation is missing, revoked or does not match the request. looks up one root hash
+	/// after another unless the source checks a batch itself
+	async fn check(&self, result: Vec<VerifyResult>) -> crate::Result<Vec<VerifyResult>> {
+		let mut v = vec![];
+		for mut i in result {
+			if i.verdict.is_valid() {
+				match self.attestation_status(i.root_hash).await {
+					Ok(status) => i.check_attestation(&status),
+					Err(e) => {
+						log::error!(
+							target: ATTESTATION_LOG_TARGET,
+							"fail to read attestation from {}|root_hash:{:}|number:{:?}|err:{:?}",
+							self.name(),
+							hex::encode(i.root_hash),
+							i.number,
+							e
+						);
+						i.verdict = Verdict::AttestationUnavailable;
+					},
+				}
+				log::info!(
+					target: ATTESTATION_LOG_TARGET,
+					"roothash: {:} | in block #{:?} | source: {} | verdict: {:?}",
+					hex::encode(i.root_hash),
+					i.number,
+					self.name(),
+					i.verdict
+				);
+			}
+			v.push(i)
+		}
+		Ok(v)
+	}
+}
@@ -1,10 +1,17 @@
 use super::{
-	Address, Contract, Deserialize, Http, IpfsClient, IpfsConfig, KiltClient, KiltConfig,
-	MoonbeamClient, MoonbeamConfig, Serialize,
+	Address, Contract, Database, Deserialize, Http, IpfsClient, IpfsConfig, KiltClient, KiltConfig,
+	MoonbeamClient, MoonbeamConfig, Serialize, VerdictPolicy,
+};
+use crate::{
+	attestation::{AttestationSource, AttestationSourceConfig},
+	kilt::AttestationCache,
+	metrics::{Metrics, MetricsConfig},
+	monitor::MonitorConfig,
+	queue::{BackpressureConfig, QueueConfig},
+	RetryPolicy,
 };
-use crate::monitor::MonitorConfig;
 use secp256k1::SecretKey;
-use std::{fs::File, path::PathBuf};
+use std::{fs::File, path::PathBuf, sync::Arc};
 
 // todo: move
 #[derive(Clone, Debug)]
@@ -18,9 +25,21 @@ pub struct ChannelFiles {
 #[derive(Clone, Debug)]
 pub struct ConfigInstance {
 	pub channel_files: ChannelFiles,
+	pub queue: QueueConfig,
+	pub backpressure: BackpressureConfig,
+	pub supervisor: SupervisorConfig,
+	pub metrics: Metrics,
 	pub moonbeam_client: MoonbeamClient,
 	pub ipfs_client: IpfsClient,
 	pub kilt_client: KiltClient,
+	// the revocation watch connects to it on every run, so a dropped connection is renewed
+	pub kilt_ws_url: Option<String>,
+	pub attestation_cache: AttestationCache,
+	// where the attest stage checks credentials, kilt unless configured otherwise
+	pub attestation_source: Arc<dyn AttestationSource>,
+	pub database: Database,
+	pub policy: VerdictPolicy,
+	pub check_attestation_on_chain: bool,
 	pub proof_contract: Contract<Http>,
 	pub aggregator_contract: Contract<Http>,
 	pub private_key: SecretKey,
@@ -34,10 +53,58 @@ pub struct Config {
 	pub moonbeam: MoonbeamConfig,
 	pub ipfs: IpfsConfig,
 	pub kilt: KiltConfig,
+	#[serde(default)]
+	pub attestation: AttestationSourceConfig,
+	// which failing verdicts are submitted on-chain
+	#[serde(default)]
+	pub policy: VerdictPolicy,
+	// backend of the channels between stages
+	#[serde(default)]
+	pub queue: QueueConfig,
+	// how much the channels may hold before the scan pauses
+	#[serde(default)]
+	pub backpressure: BackpressureConfig,
+	// restarts of failed tasks
+	#[serde(default)]
+	pub supervisor: SupervisorConfig,
+	// prometheus endpoint
+	#[serde(default)]
+	pub metrics: MetricsConfig,
 	#[cfg(feature = "monitor")]
 	pub monitor: MonitorConfig,
 }
 
+fn default_max_restarts() -> u32 {
+	5
+}
+
+fn default_window_secs() -> u64 {
+	600
+}
+
+/// how failed tasks are restarted, and when the keeper gives up on them
+#[derive(Eq, PartialEq, Clone, Debug, Deserialize, Serialize)]
+pub struct SupervisorConfig {
+	// the keeper shuts down once a task fails more often within the window
+	#[serde(default = "default_max_restarts")]
+	pub max_restarts: u32,
+	#[serde(default = "default_window_secs")]
+	pub window_secs: u64,
+	// delay before a restart, growing with the failures in the window
+	#[serde(default)]
+	pub backoff: RetryPolicy,
+}
+
+impl Default for SupervisorConfig {
+	fn default() -> Self {
+		SupervisorConfig {
+			max_restarts: default_max_restarts(),
+			window_secs: default_window_secs(),
+			backoff: Default::default(),
+		}
+	}
+}
+
 impl Config {
 	pub fn load_from_json(config_path: &PathBuf) -> Result<Self> {
 		let file = File::open(config_path)?;
@@ -69,16 +136,41 @@ mod tests {
 	fn config_parse_should_work() {
 		let path = PathBuf::from("./res/config-example.json");
 		let config = Config::load_from_json(&path).unwrap();
-		use crate::{IpfsConfig, KiltConfig, MoonbeamConfig};
+		use crate::{
+			metrics::MetricsConfig,
+			queue::{BackpressureConfig, WaterMarks},
+			IpfsConfig, KiltConfig, MoonbeamConfig, RetryPolicy, SupervisorConfig,
+		};
 		let expect = Config {
 			moonbeam: MoonbeamConfig {
 				url: "http://127.0.0.1:7545".to_string(),
 				read_contract: "read_contract".to_string(),
 				write_contract: "write_contract".to_string(),
 				private_key: "private_key".to_string(),
+				retry: Default::default(),
+			},
+			ipfs: IpfsConfig {
+				base_url: "https://ipfs.infura.io:5001".to_string(),
+				retry: RetryPolicy { max_attempts: 3, jitter: false, ..Default::default() },
+			},
+			kilt: KiltConfig {
+				url: "kilt_url".to_string(),
+				ws_url: None,
+				trust: Default::default(),
+				cache: Default::default(),
+				checkpoint: None,
+				check_on_chain: false,
+				retry: Default::default(),
+			},
+			attestation: Default::default(),
+			policy: Default::default(),
+			queue: Default::default(),
+			backpressure: BackpressureConfig {
+				event_to_ipfs: Some(WaterMarks { high: 1000, low: 100 }),
+				..Default::default()
 			},
-			ipfs: IpfsConfig { base_url: "https://ipfs.infura.io:5001".to_string() },
-			kilt: KiltConfig { url: "kilt_url".to_string() },
+			supervisor: SupervisorConfig { max_restarts: 3, ..Default::default() },
+			metrics: MetricsConfig { listen: Some("127.0.0.1:9615".parse().unwrap()) },
 		};
 
 		assert_eq!(config, expect);
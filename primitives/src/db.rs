@@ -0,0 +1,489 @@
+use std::{
+	collections::BTreeSet,
+	path::Path,
+	time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use sled::{
+	transaction::{ConflictableTransactionError, TransactionError},
+	Transactional,
+};
+
+use super::{
+	dead_letter::DeadLetter, kilt::CachedAttestation, Bytes32, Deserialize, Events, Hash,
+	Idempotent, ProofEvent, Serialize, Stage, VerifyResult, U64,
+};
+
+pub const DB_LOG_TARGET: &str = "Database";
+
+// tree names
+const VERIFY_CACHE_TREE: &str = "verify_cache";
+const VERDICT_TREE: &str = "verdicts";
+const APPROVED_TREE: &str = "approved_attestations";
+const REVOCATION_TREE: &str = "revocations";
+const ATTESTATION_CACHE_TREE: &str = "attestation_cache";
+const SCANNED_TREE: &str = "scanned_events";
+const META_TREE: &str = "meta";
+
+// keys in the meta tree
+const SCAN_CHECKPOINT_KEY: &[u8] = b"scan_checkpoint";
+
+/// an attestation revoked after the keeper submitted passing verdicts relying on it
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct RevocationRecord {
+	pub root_hash: Bytes32,
+	// kilt block the revocation is observed at
+	pub kilt_block_hash: Bytes32,
+	// unix timestamp in seconds
+	pub observed_at: u64,
+	// requests approved with this attestation
+	pub request_hashes: Vec<Bytes32>,
+}
+
+/// keeper's local persistent state, lives in the `cache_dir`
+#[derive(Clone, Debug)]
+pub struct Database {
+	inner: sled::Db,
+	verify_cache: sled::Tree,
+	verdicts: sled::Tree,
+	approved: sled::Tree,
+	revocations: sled::Tree,
+	attestation_cache: sled::Tree,
+	scanned: sled::Tree,
+	meta: sled::Tree,
+}
+
+impl Database {
+	pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+		let inner = sled::open(path)?;
+		Self::from_sled(inner)
+	}
+
+	/// in-memory database, dropped with the instance
+	pub fn temporary() -> Result<Self> {
+		let inner = sled::Config::new().temporary(true).open()?;
+		Self::from_sled(inner)
+	}
+
+	fn from_sled(inner: sled::Db) -> Result<Self> {
+		let verify_cache = inner.open_tree(VERIFY_CACHE_TREE)?;
+		let verdicts = inner.open_tree(VERDICT_TREE)?;
+		let approved = inner.open_tree(APPROVED_TREE)?;
+		let revocations = inner.open_tree(REVOCATION_TREE)?;
+		let attestation_cache = inner.open_tree(ATTESTATION_CACHE_TREE)?;
+		let scanned = inner.open_tree(SCANNED_TREE)?;
+		let meta = inner.open_tree(META_TREE)?;
+		Ok(Database {
+			inner,
+			verify_cache,
+			verdicts,
+			approved,
+			revocations,
+			attestation_cache,
+			scanned,
+			meta,
+		})
+	}
+
+	/// cached StarksVM verification result, see `verify::verify_cache_key`
+	pub fn verify_result(&self, key: &Bytes32) -> Result<Option<bool>> {
+		let maybe_result = self.verify_cache.get(key)?;
+		Ok(maybe_result.map(|v| v.first() == Some(&1u8)))
+	}
+
+	pub fn insert_verify_result(&self, key: &Bytes32, passed: bool) -> Result<()> {
+		self.verify_cache.insert(key, vec![passed as u8])?;
+		Ok(())
+	}
+
+	/// final verdict of a request, keyed by request hash
+	pub fn verdict(&self, request_hash: &Bytes32) -> Result<Option<VerifyResult>> {
+		match self.verdicts.get(request_hash)? {
+			Some(v) => Ok(Some(serde_json::from_slice(&v)?)),
+			None => Ok(None),
+		}
+	}
+
+	pub fn insert_verdict(&self, result: &VerifyResult) -> Result<()> {
+		self.verdicts.insert(result.request_hash, serde_json::to_vec(result)?)?;
+		Ok(())
+	}
+
+	/// passing verdict submitted on chain, keyed by root hash ++ request hash
+	pub fn insert_approved(&self, result: &VerifyResult) -> Result<()> {
+		let key = [result.root_hash, result.request_hash].concat();
+		self.approved.insert(key, serde_json::to_vec(result)?)?;
+		Ok(())
+	}
+
+	/// root hashes of attestations with at least one approved request
+	pub fn approved_root_hashes(&self) -> Result<BTreeSet<Bytes32>> {
+		let mut root_hashes = BTreeSet::new();
+		for entry in self.approved.iter() {
+			let (key, _) = entry?;
+			let mut root_hash = Bytes32::default();
+			root_hash.copy_from_slice(&key[..32]);
+			root_hashes.insert(root_hash);
+		}
+		Ok(root_hashes)
+	}
+
+	/// approved requests relying on the attestation
+	pub fn approved(&self, root_hash: &Bytes32) -> Result<Vec<VerifyResult>> {
+		let mut results = vec![];
+		for entry in self.approved.scan_prefix(root_hash) {
+			let (_, value) = entry?;
+			results.push(serde_json::from_slice(&value)?);
+		}
+		Ok(results)
+	}
+
+	pub fn revocation(&self, root_hash: &Bytes32) -> Result<Option<RevocationRecord>> {
+		match self.revocations.get(root_hash)? {
+			Some(v) => Ok(Some(serde_json::from_slice(&v)?)),
+			None => Ok(None),
+		}
+	}
+
+	/// record the revocation and drop the approved requests it lists, in one transaction
+	pub fn insert_revocation(&self, record: &RevocationRecord) -> Result<()> {
+		let value = serde_json::to_vec(record)?;
+		(&self.approved, &self.revocations)
+			.transaction(|(approved, revocations)| {
+				for request_hash in &record.request_hashes {
+					approved.remove([record.root_hash, *request_hash].concat())?;
+				}
+				revocations.insert(record.root_hash.to_vec(), value.clone())?;
+				Ok::<_, ConflictableTransactionError>(())
+			})
+			.map_err(|e| match e {
+				// the closure never aborts
+				TransactionError::Abort(()) => unreachable!(),
+				TransactionError::Storage(e) => Error::SledError(e),
+			})
+	}
+
+	/// persisted entry of `kilt::AttestationCache`, keyed by root hash
+	pub fn cached_attestation(&self, root_hash: &Hash) -> Result<Option<CachedAttestation>> {
+		match self.attestation_cache.get(root_hash)? {
+			Some(v) => Ok(Some(serde_json::from_slice(&v)?)),
+			None => Ok(None),
+		}
+	}
+
+	pub fn insert_cached_attestation(
+		&self,
+		root_hash: &Hash,
+		entry: &CachedAttestation,
+	) -> Result<()> {
+		self.attestation_cache.insert(root_hash, serde_json::to_vec(entry)?)?;
+		Ok(())
+	}
+
+	pub fn cached_attestations(&self) -> Result<Vec<(Hash, CachedAttestation)>> {
+		let mut entries = vec![];
+		for entry in self.attestation_cache.iter() {
+			let (key, value) = entry?;
+			entries.push((Hash::from_slice(&key), serde_json::from_slice(&value)?));
+		}
+		Ok(entries)
+	}
+
+	pub fn remove_cached_attestation(&self, root_hash: &Hash) -> Result<()> {
+		self.attestation_cache.remove(root_hash)?;
+		Ok(())
+	}
+
+	/// move an unprocessable message of the stage aside, returns its id
+	pub fn insert_dead_letter(&self, stage: Stage, error: String, payload: &[u8]) -> Result<u64> {
+		let id = self.inner.generate_id()?;
+		let letter = DeadLetter::new(id, stage, error, payload.to_vec());
+		self.dead_letter_tree(stage)?
+			.insert(id.to_be_bytes(), serde_json::to_vec(&letter)?)?;
+		Ok(id)
+	}
+
+	/// dead letters of the stage, oldest first
+	pub fn dead_letters(&self, stage: Stage) -> Result<Vec<DeadLetter>> {
+		let mut letters = vec![];
+		for entry in self.dead_letter_tree(stage)?.iter() {
+			let (_, value) = entry?;
+			letters.push(serde_json::from_slice(&value)?);
+		}
+		Ok(letters)
+	}
+
+	pub fn dead_letter(&self, stage: Stage, id: u64) -> Result<Option<DeadLetter>> {
+		match self.dead_letter_tree(stage)?.get(id.to_be_bytes())? {
+			Some(v) => Ok(Some(serde_json::from_slice(&v)?)),
+			None => Ok(None),
+		}
+	}
+
+	pub fn remove_dead_letter(&self, stage: Stage, id: u64) -> Result<Option<DeadLetter>> {
+		match self.dead_letter_tree(stage)?.remove(id.to_be_bytes())? {
+			Some(v) => Ok(Some(serde_json::from_slice(&v)?)),
+			None => Ok(None),
+		}
+	}
+
+	fn dead_letter_tree(&self, stage: Stage) -> Result<sled::Tree> {
+		Ok(self.inner.open_tree(stage.dead_letter_tree())?)
+	}
+
+	/// drop items the stage has already handled, e.g. before a crash prevented the commit
+	pub fn unprocessed<T: Idempotent>(&self, stage: Stage, items: Vec<T>) -> Result<Vec<T>> {
+		let tree = self.inner.open_tree(stage.processed_tree())?;
+		let mut fresh = vec![];
+		for item in items {
+			if tree.contains_key(item.idempotency_key())? {
+				log::info!(
+					target: DB_LOG_TARGET,
+					"skip request already processed by {} stage, key: {}",
+					stage,
+					hex::encode(item.idempotency_key())
+				);
+			} else {
+				fresh.push(item);
+			}
+		}
+		Ok(fresh)
+	}
+
+	/// value is the unix timestamp in seconds the item is processed at
+	pub fn mark_processed<T: Idempotent>(&self, stage: Stage, item: &T) -> Result<()> {
+		self.inner
+			.open_tree(stage.processed_tree())?
+			.insert(item.idempotency_key(), serde_json::to_vec(&now())?)?;
+		Ok(())
+	}
+
+	/// forget items processed `retention` ago or earlier, their messages are long committed.
+	/// returns the number of items dropped
+	pub fn prune_processed(&self, stage: Stage, retention: Duration) -> Result<usize> {
+		let tree = self.inner.open_tree(stage.processed_tree())?;
+		let before = now().saturating_sub(retention.as_secs());
+		let mut pruned = 0;
+		for entry in tree.iter() {
+			let (key, value) = entry?;
+			// entries without a timestamp are left from before it was recorded
+			let processed_at = serde_json::from_slice::<u64>(&value).unwrap_or_default();
+			if processed_at <= before {
+				tree.remove(key)?;
+				pruned += 1;
+			}
+		}
+		Ok(pruned)
+	}
+
+	/// drop events already sent to the ipfs stage, e.g. logs of a re-scanned block
+	pub fn unscanned(&self, events: Events) -> Result<Events> {
+		let mut fresh = vec![];
+		for event in events {
+			if self.scanned.contains_key(event.idempotency_key())? {
+				log::debug!(
+					target: DB_LOG_TARGET,
+					"skip event already scanned, tx: {:?}, log index: {:?}",
+					event.transaction_hash(),
+					event.log_index()
+				);
+			} else {
+				fresh.push(event);
+			}
+		}
+		Ok(fresh)
+	}
+
+	/// value is the block number the event is emitted in
+	pub fn mark_scanned(&self, event: &ProofEvent) -> Result<()> {
+		self.scanned
+			.insert(event.idempotency_key(), serde_json::to_vec(&event.block_number())?)?;
+		Ok(())
+	}
+
+	/// forget events emitted before `block`, the scan does not go back past its checkpoint.
+	/// returns the number of events dropped
+	pub fn prune_scanned(&self, block: U64) -> Result<usize> {
+		let mut pruned = 0;
+		for entry in self.scanned.iter() {
+			let (key, value) = entry?;
+			let number: Option<U64> = serde_json::from_slice(&value)?;
+			if number.map_or(true, |n| n < block) {
+				self.scanned.remove(key)?;
+				pruned += 1;
+			}
+		}
+		Ok(pruned)
+	}
+
+	/// next moonbeam block to scan
+	pub fn scan_checkpoint(&self) -> Result<Option<U64>> {
+		let maybe_block = self.meta.get(SCAN_CHECKPOINT_KEY)?;
+		Ok(maybe_block.map(|v| serde_json::from_slice(&v)).transpose()?)
+	}
+
+	pub fn set_scan_checkpoint(&self, block: U64) -> Result<()> {
+		self.meta.insert(SCAN_CHECKPOINT_KEY, serde_json::to_vec(&block)?)?;
+		Ok(())
+	}
+
+	pub fn flush(&self) -> Result<()> {
+		self.inner.flush()?;
+		Ok(())
+	}
+}
+
+fn now() -> u64 {
+	SystemTime::now()
+		.duration_since(UNIX_EPOCH)
+		.map(|d| d.as_secs())
+		.unwrap_or_default()
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+	#[error("Sled database error, err: {0}")]
+	SledError(#[from] sled::Error),
+	#[error("Database value codec error, err: {0}")]
+	JsonError(#[from] serde_json::Error),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[cfg(test)]
+mod tests {
+	use std::time::Duration;
+
+	use super::{Database, RevocationRecord};
+	use crate::{ProofEvent, Stage, TxHash, Verdict, VerifyResult};
+
+	#[test]
+	fn verify_cache_should_work() {
+		let db = Database::temporary().expect("fail to open temporary database");
+		let key = [7u8; 32];
+		assert_eq!(db.verify_result(&key).unwrap(), None);
+
+		db.insert_verify_result(&key, true).unwrap();
+		assert_eq!(db.verify_result(&key).unwrap(), Some(true));
+
+		db.insert_verify_result(&key, false).unwrap();
+		assert_eq!(db.verify_result(&key).unwrap(), Some(false));
+	}
+
+	#[test]
+	fn verdict_record_should_work() {
+		let db = Database::temporary().expect("fail to open temporary database");
+		let result = VerifyResult {
+			request_hash: [1u8; 32],
+			verdict: Verdict::AttestationRevoked,
+			..Default::default()
+		};
+		assert_eq!(db.verdict(&result.request_hash).unwrap(), None);
+
+		db.insert_verdict(&result).unwrap();
+		assert_eq!(db.verdict(&result.request_hash).unwrap(), Some(result));
+	}
+
+	#[test]
+	fn approved_attestation_should_be_dropped_with_its_revocation() {
+		let db = Database::temporary().expect("fail to open temporary database");
+		let first = VerifyResult {
+			root_hash: [1u8; 32],
+			request_hash: [2u8; 32],
+			verdict: Verdict::Valid,
+			..Default::default()
+		};
+		let second = VerifyResult { request_hash: [3u8; 32], ..first.clone() };
+		let other = VerifyResult { root_hash: [4u8; 32], ..first.clone() };
+		for result in [&first, &second, &other] {
+			db.insert_approved(result).unwrap();
+		}
+		assert_eq!(
+			db.approved_root_hashes().unwrap().into_iter().collect::<Vec<_>>(),
+			vec![[1u8; 32], [4u8; 32]]
+		);
+
+		assert_eq!(db.approved(&[1u8; 32]).unwrap(), vec![first, second]);
+
+		let record = RevocationRecord {
+			root_hash: [1u8; 32],
+			request_hashes: vec![[2u8; 32], [3u8; 32]],
+			..Default::default()
+		};
+		db.insert_revocation(&record).unwrap();
+		assert_eq!(db.revocation(&[1u8; 32]).unwrap(), Some(record));
+		assert!(db.approved(&[1u8; 32]).unwrap().is_empty());
+		assert_eq!(db.approved_root_hashes().unwrap().len(), 1);
+	}
+
+	#[test]
+	fn dead_letters_should_be_kept_per_stage() {
+		let db = Database::temporary().expect("fail to open temporary database");
+		let first = db.insert_dead_letter(Stage::Kilt, "bad json".to_owned(), b"{").unwrap();
+		let second = db.insert_dead_letter(Stage::Kilt, "bad json".to_owned(), b"[").unwrap();
+		db.insert_dead_letter(Stage::Ipfs, "bad events".to_owned(), b"]").unwrap();
+
+		let letters = db.dead_letters(Stage::Kilt).unwrap();
+		assert_eq!(letters.iter().map(|l| l.id).collect::<Vec<_>>(), vec![first, second]);
+		assert_eq!(letters[0].payload, b"{".to_vec());
+		assert_eq!(db.dead_letter(Stage::Kilt, second).unwrap().unwrap().payload, b"[".to_vec());
+		assert!(db.dead_letters(Stage::Submit).unwrap().is_empty());
+
+		assert_eq!(db.remove_dead_letter(Stage::Kilt, first).unwrap().unwrap().error, "bad json");
+		assert!(db.remove_dead_letter(Stage::Kilt, first).unwrap().is_none());
+		assert_eq!(db.dead_letters(Stage::Kilt).unwrap().len(), 1);
+	}
+
+	#[test]
+	fn rescanned_events_should_be_skipped() {
+		let db = Database::temporary().expect("fail to open temporary database");
+		let event = ProofEvent {
+			block_number: Some(10.into()),
+			transaction_hash: Some(TxHash::repeat_byte(1)),
+			log_index: Some(0.into()),
+			..Default::default()
+		};
+		// same request emitted again in a later log
+		let next = ProofEvent { log_index: Some(1.into()), ..event.clone() };
+
+		assert_eq!(db.unscanned(vec![event.clone()]).unwrap(), vec![event.clone()]);
+		db.mark_scanned(&event).unwrap();
+		assert_eq!(db.unscanned(vec![event.clone(), next.clone()]).unwrap(), vec![next]);
+
+		assert_eq!(db.prune_scanned(10.into()).unwrap(), 0);
+		assert!(db.unscanned(vec![event.clone()]).unwrap().is_empty());
+		assert_eq!(db.prune_scanned(11.into()).unwrap(), 1);
+		assert_eq!(db.unscanned(vec![event.clone()]).unwrap(), vec![event]);
+	}
+
+	#[test]
+	fn processed_requests_should_be_skipped_until_pruned() {
+		let db = Database::temporary().expect("fail to open temporary database");
+		let result = VerifyResult {
+			transaction_hash: Some(TxHash::repeat_byte(1)),
+			log_index: Some(0.into()),
+			..Default::default()
+		};
+		let next = VerifyResult { log_index: Some(1.into()), ..result.clone() };
+
+		db.mark_processed(Stage::Kilt, &result).unwrap();
+		let fresh = db.unprocessed(Stage::Kilt, vec![result.clone(), next.clone()]).unwrap();
+		assert_eq!(fresh, vec![next]);
+		// kept per stage
+		assert_eq!(db.unprocessed(Stage::Submit, vec![result.clone()]).unwrap().len(), 1);
+
+		assert_eq!(db.prune_processed(Stage::Kilt, Duration::from_secs(3600)).unwrap(), 0);
+		assert!(db.unprocessed(Stage::Kilt, vec![result.clone()]).unwrap().is_empty());
+		assert_eq!(db.prune_processed(Stage::Kilt, Duration::ZERO).unwrap(), 1);
+		assert_eq!(db.unprocessed(Stage::Kilt, vec![result]).unwrap().len(), 1);
+	}
+
+	#[test]
+	fn scan_checkpoint_should_be_overwritten() {
+		let db = Database::temporary().expect("fail to open temporary database");
+		assert_eq!(db.scan_checkpoint().unwrap(), None);
+		db.set_scan_checkpoint(10.into()).unwrap();
+		db.set_scan_checkpoint(21.into()).unwrap();
+		assert_eq!(db.scan_checkpoint().unwrap(), Some(21.into()));
+	}
+}
@@ -0,0 +1,26 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::{Deserialize, Serialize, Stage};
+
+pub const DEAD_LETTER_LOG_TARGET: &str = "DeadLetter";
+
+/// a message the stage could not parse, moved aside so the channel keeps going
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeadLetter {
+	pub id: u64,
+	pub stage: Stage,
+	pub error: String,
+	// unix timestamp in seconds
+	pub timestamp: u64,
+	pub payload: Vec<u8>,
+}
+
+impl DeadLetter {
+	pub fn new(id: u64, stage: Stage, error: String, payload: Vec<u8>) -> Self {
+		let timestamp = SystemTime::now()
+			.duration_since(UNIX_EPOCH)
+			.map(|d| d.as_secs())
+			.unwrap_or_default();
+		DeadLetter { id, stage, error, timestamp, payload }
+	}
+}
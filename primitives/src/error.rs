@@ -1,3 +1,10 @@
+use std::fmt;
+
+use crate::{
+	ipfs::Error as IpfsError, kilt::Error as KiltError, moonbeam::Error as MoonbeamError, Bytes32,
+	Retryable, Stage, U64,
+};
+
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
 	#[error("Config load Error, err: {0}")]
@@ -6,6 +13,9 @@ pub enum Error {
 	#[error("msg queue file I/O error,  err: {0}")]
 	IoError(#[from] std::io::Error),
 
+	#[error("Message queue Error, err: {0}")]
+	QueueError(#[from] crate::queue::Error),
+
 	#[error("Event Parse Error, err: {0}")]
 	EventParseError(#[from] serde_json::Error),
 
@@ -18,6 +28,9 @@ pub enum Error {
 	#[error("StarksVM Verify Error, err: {0}")]
 	StarksVMError(#[from] crate::verify::Error),
 
+	#[error("Local database Error, err: {0}")]
+	DatabaseError(#[from] crate::db::Error),
+
 	#[error("Fetch Kilt attestation Error, err: {0}")]
 	KiltError(#[from] crate::kilt::Error),
 
@@ -30,3 +43,211 @@ pub enum Error {
 	#[error("Task error, err: {0}")]
 	TaskJoinError(#[from] tokio::task::JoinError),
 }
+
+/// how the keeper reacts to an error
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorKind {
+	/// a service is slow or out of reach, the same work succeeds later
+	Transient,
+	/// the request at hand can never be handled, it is set aside and the others go on
+	Item,
+	/// the keeper can not go on, e.g. a wrong config or a broken database
+	Fatal,
+}
+
+impl Error {
+	pub fn kind(&self) -> ErrorKind {
+		match self {
+			Error::MoonbeamError(e) => match e {
+				// the node may answer later, whatever it failed with
+				MoonbeamError::Web3Error(_) => ErrorKind::Transient,
+				MoonbeamError::Web3ContractError(web3::contract::Error::Api(_)) =>
+					ErrorKind::Transient,
+				MoonbeamError::Web3ContractError(_) | MoonbeamError::EthAbiError(_) =>
+					ErrorKind::Item,
+				MoonbeamError::ClientCreationError(_) |
+				MoonbeamError::InvalidEthereumAddress(_) => ErrorKind::Fatal,
+			},
+			Error::IpfsError(e) => match e {
+				IpfsError::HttpError(_) => ErrorKind::Transient,
+				IpfsError::UrlError(_) => ErrorKind::Item,
+				IpfsError::InvalidIpfsHost | IpfsError::SchemeError => ErrorKind::Fatal,
+			},
+			Error::KiltError(e) => match e {
+				KiltError::KiltClientError(_) |
+				KiltError::SubscriptionClosed |
+				KiltError::HeaderChainError(_) => ErrorKind::Transient,
+				KiltError::Serialization(_) |
+				KiltError::StorageValueDecode(_) |
+				KiltError::StorageValueMissing(_) |
+				KiltError::RevokedAfterApproval(_) |
+				KiltError::StorageProofError(_) => ErrorKind::Item,
+				KiltError::UrlFormatError(_) |
+				KiltError::UnsupportedMetadata(_) |
+				KiltError::UnsupportedStorageLayout(_) => ErrorKind::Fatal,
+			},
+			Error::QueueError(e) if e.is_retryable() => ErrorKind::Transient,
+			Error::EventParseError(_) | Error::StarksVMError(_) => ErrorKind::Item,
+			Error::ConfigLoadError(_) |
+			Error::IoError(_) |
+			Error::QueueError(_) |
+			Error::DatabaseError(_) |
+			Error::OtherError(_) |
+			Error::PrivateKeyError(_) |
+			Error::TaskJoinError(_) => ErrorKind::Fatal,
+		}
+	}
+}
+
+/// an error with the work it interrupted, as far as it is known
+#[derive(Debug)]
+pub struct KeeperError {
+	pub error: Error,
+	pub stage: Option<Stage>,
+	pub block: Option<U64>,
+	pub request_hash: Option<Bytes32>,
+	pub cid: Option<String>,
+}
+
+impl KeeperError {
+	pub fn new(error: Error) -> Self {
+		KeeperError { error, stage: None, block: None, request_hash: None, cid: None }
+	}
+
+	pub fn kind(&self) -> ErrorKind {
+		self.error.kind()
+	}
+
+	// context closer to the error is kept, callers only fill in what is missing
+
+	pub fn in_stage(mut self, stage: Stage) -> Self {
+		self.stage = self.stage.or(Some(stage));
+		self
+	}
+
+	pub fn at_block(mut self, block: Option<U64>) -> Self {
+		self.block = self.block.or(block);
+		self
+	}
+
+	pub fn for_request(mut self, request_hash: Bytes32) -> Self {
+		self.request_hash = self.request_hash.or(Some(request_hash));
+		self
+	}
+
+	pub fn with_cid(mut self, cid: &str) -> Self {
+		self.cid = self.cid.or_else(|| Some(cid.to_owned()));
+		self
+	}
+}
+
+impl fmt::Display for KeeperError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "{}", self.error)?;
+		if let Some(stage) = self.stage {
+			write!(f, " | stage: {}", stage)?;
+		}
+		if let Some(block) = self.block {
+			write!(f, " | block: {}", block)?;
+		}
+		if let Some(request_hash) = self.request_hash {
+			write!(f, " | request hash: {}", hex::encode(request_hash))?;
+		}
+		if let Some(cid) = &self.cid {
+			write!(f, " | cid: {}", cid)?;
+		}
+		Ok(())
+	}
+}
+
+impl std::error::Error for KeeperError {
+	fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+		Some(&self.error)
+	}
+}
+
+// `?` converts the errors of every component, without context
+macro_rules! impl_from_for_keeper_error {
+	($($error:ty),* $(,)?) => {
+		$(
+			impl From<$error> for KeeperError {
+				fn from(e: $error) -> Self {
+					KeeperError::new(e.into())
+				}
+			}
+		)*
+	};
+}
+
+impl_from_for_keeper_error!(
+	Error,
+	crate::config::Error,
+	std::io::Error,
+	crate::queue::Error,
+	serde_json::Error,
+	crate::moonbeam::Error,
+	crate::ipfs::Error,
+	crate::verify::Error,
+	crate::db::Error,
+	crate::kilt::Error,
+	secp256k1::Error,
+	tokio::task::JoinError,
+);
+
+/// attach what was being worked on to the error of a result
+pub trait ErrorContext<T> {
+	fn in_stage(self, stage: Stage) -> crate::Result<T>;
+	fn at_block(self, block: Option<U64>) -> crate::Result<T>;
+	fn for_request(self, request_hash: Bytes32) -> crate::Result<T>;
+	fn with_cid(self, cid: &str) -> crate::Result<T>;
+}
+
+impl<T, E: Into<KeeperError>> ErrorContext<T> for Result<T, E> {
+	fn in_stage(self, stage: Stage) -> crate::Result<T> {
+		self.map_err(|e| e.into().in_stage(stage))
+	}
+
+	fn at_block(self, block: Option<U64>) -> crate::Result<T> {
+		self.map_err(|e| e.into().at_block(block))
+	}
+
+	fn for_request(self, request_hash: Bytes32) -> crate::Result<T> {
+		self.map_err(|e| e.into().for_request(request_hash))
+	}
+
+	fn with_cid(self, cid: &str) -> crate::Result<T> {
+		self.map_err(|e| e.into().with_cid(cid))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use jsonrpsee::types::Error as RpcError;
+
+	use super::{Error, ErrorContext, ErrorKind, KeeperError};
+	use crate::{ipfs::Error as IpfsError, kilt::Error as KiltError, Stage};
+
+	#[test]
+	fn errors_should_be_classified() {
+		let timeout = KiltError::KiltClientError(RpcError::RequestTimeout);
+		assert_eq!(Error::KiltError(timeout).kind(), ErrorKind::Transient);
+		let missing = KiltError::StorageValueMissing("0x00".to_owned());
+		assert_eq!(Error::KiltError(missing).kind(), ErrorKind::Item);
+		let proof = crate::verify::Error::VerifyError("bad proof".to_owned());
+		assert_eq!(Error::StarksVMError(proof).kind(), ErrorKind::Item);
+		assert_eq!(Error::IpfsError(IpfsError::InvalidIpfsHost).kind(), ErrorKind::Fatal);
+		assert_eq!(Error::OtherError("config".to_owned()).kind(), ErrorKind::Fatal);
+	}
+
+	#[test]
+	fn inner_context_should_be_kept() {
+		let res: Result<(), KiltError> = Err(KiltError::SubscriptionClosed);
+		let e = res.at_block(Some(7.into())).in_stage(Stage::Kilt).at_block(None).unwrap_err();
+		assert_eq!(e.block, Some(7.into()));
+		assert_eq!(e.stage, Some(Stage::Kilt));
+
+		let e = KeeperError::from(IpfsError::SchemeError).with_cid("Qm").with_cid("other");
+		assert_eq!(e.cid.as_deref(), Some("Qm"));
+		assert_eq!(e.to_string(), format!("{} | cid: Qm", e.error));
+	}
+}
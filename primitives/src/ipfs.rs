@@ -1,21 +1,23 @@
-use super::{Deserialize, Serialize};
+use super::{Deserialize, RetryPolicy, Retryable, Serialize};
 use reqwest::Client;
 use std::time::Duration;
 
 pub const IPFS_LOG_TARGET: &str = "IPFS";
 
-// ipfs max retry times
-const IPFS_MAX_RETRY_TIMES: usize = 5;
 const TIME_OUT: Duration = Duration::from_secs(5);
 // TODO:
 const INFURA_USERNAME: &str = "26pucpYcATVSbrd7Cfvjwi2XcwT";
 const INFURA_PASSWORD: &str = "9b3ca935d5c247e3fa9542f713498c91";
 const IPFS_CAT_PATH: &str = "api/v0/cat";
+// rate limited or the gateway is down, the content may still be there
+const RETRY_STATUS: [u16; 4] = [429, 502, 503, 504];
 
 #[derive(Eq, PartialEq, Clone, Debug, Deserialize, Serialize)]
 pub struct IpfsConfig {
 	// e.g.  https://ipfs.infura.io:5001
 	pub base_url: String,
+	#[serde(default)]
+	pub retry: RetryPolicy,
 }
 
 // fixme: remove?
@@ -24,6 +26,8 @@ pub struct IpfsClient {
 	// e.g.  https://ipfs.infura.io:5001/api/v0/cat
 	pub cat_url_prefix: String,
 	pub ip_address: String,
+	#[serde(default)]
+	pub retry: RetryPolicy,
 }
 
 impl IpfsClient {
@@ -38,48 +42,52 @@ impl IpfsClient {
 			return Ok(IpfsClient {
 				cat_url_prefix: cat_url + IPFS_CAT_PATH,
 				ip_address: String::from(config_base_url),
+				retry: Default::default(),
 			})
 		} else {
 			return Err(Error::InvalidIpfsHost)
 		}
 	}
 
+	pub fn with_retry(mut self, retry: RetryPolicy) -> Self {
+		self.retry = retry;
+		self
+	}
+
 	pub async fn fetch_proof(&self, cid: &str) -> Result<Vec<u8>> {
 		log::info!(target: IPFS_LOG_TARGET, "Start querying ipfs cid : {:?}", cid);
 
 		let client = Client::builder().connect_timeout(TIME_OUT).build()?;
-		keep_fetch(&self.cat_url_prefix, cid, client).await
+		keep_fetch(&self.cat_url_prefix, cid, &client, &self.retry).await
 	}
 }
 
-async fn keep_fetch(base_url: &str, cid: &str, client: Client) -> Result<Vec<u8>> {
+async fn keep_fetch(
+	base_url: &str,
+	cid: &str,
+	client: &Client,
+	retry: &RetryPolicy,
+) -> Result<Vec<u8>> {
 	// TODO: make it config?
-	let params = [("arg", cid)];
-	let mut body = String::new();
-
-	for i in 0..IPFS_MAX_RETRY_TIMES {
-		let maybe_response = client
+	let params = &[("arg", cid)];
+	let fetch = move || async move {
+		let response = client
 			.post(base_url)
-			.query(&params)
+			.query(params)
 			.basic_auth(INFURA_USERNAME, Some(INFURA_PASSWORD))
 			.send()
-			.await;
-		match maybe_response {
-			Ok(r) => {
-				body = r.text().await?;
-				break
-			},
-			Err(e) => {
-				if e.is_timeout() && i < (IPFS_MAX_RETRY_TIMES - 1) {
-					log::warn!("ipfs client fetch data timeout! retry: {:} ...", i + 1);
-					continue
-				}
-				log::error!("ipfs client fetch data error. reason: {:?}", e);
-				Err(e)?
-			},
-		}
-	}
-	Ok(body.into_bytes())
+			.await?
+			// content ipfs can not find is answered with an error status
+			.error_for_status()?;
+		Ok::<_, Error>(response.text().await?.into_bytes())
+	};
+	retry
+		.retry(IPFS_LOG_TARGET, "ipfs client fetch data", fetch)
+		.await
+		.map_err(|e| {
+			log::error!(target: IPFS_LOG_TARGET, "ipfs client fetch data error. reason: {:?}", e);
+			e
+		})
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -94,4 +102,16 @@ pub enum Error {
 	SchemeError,
 }
 
+impl Retryable for Error {
+	fn is_retryable(&self) -> bool {
+		match self {
+			Error::HttpError(e) => {
+				let retry_status = e.status().map_or(false, |s| RETRY_STATUS.contains(&s.as_u16()));
+				e.is_timeout() || e.is_connect() || retry_status
+			},
+			_ => false,
+		}
+	}
+}
+
 pub type Result<T> = std::result::Result<T, Error>;
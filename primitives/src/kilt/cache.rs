@@ -0,0 +1,241 @@
+use std::{
+	collections::{BTreeSet, HashMap},
+	sync::{Arc, Mutex},
+	time::{SystemTime, UNIX_EPOCH},
+};
+
+use super::{Attestation, Deserialize, Hash, Serialize, KILT_LOG_TARGET};
+use crate::Database;
+
+/// ttl cap of a cache no revocation watch invalidates, a revoked attestation
+/// may still be served as valid for up to this long
+pub const MAX_UNWATCHED_TTL_SECS: u64 = 60;
+
+fn default_ttl_secs() -> u64 {
+	0
+}
+
+fn default_negative_ttl_secs() -> u64 {
+	30
+}
+
+#[derive(Eq, PartialEq, Clone, Debug, Deserialize, Serialize)]
+pub struct AttestationCacheConfig {
+	// 0 disables the cache, the default
+	#[serde(default = "default_ttl_secs")]
+	pub ttl_secs: u64,
+	// ttl of root hashes without attestation, which may be attested soon
+	#[serde(default = "default_negative_ttl_secs")]
+	pub negative_ttl_secs: u64,
+	// keep entries in the database across restarts
+	#[serde(default)]
+	pub persistent: bool,
+}
+
+impl Default for AttestationCacheConfig {
+	fn default() -> Self {
+		AttestationCacheConfig {
+			ttl_secs: default_ttl_secs(),
+			negative_ttl_secs: default_negative_ttl_secs(),
+			persistent: false,
+		}
+	}
+}
+
+/// attestation read from kilt, `None` if there was no attestation
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CachedAttestation {
+	pub attestation: Option<Attestation>,
+	// kilt block the attestation is read at
+	pub at: Hash,
+	// unix timestamp in seconds
+	pub cached_at: u64,
+}
+
+/// cache of `Attestation::Attestations` lookups, shared by all kilt tasks
+#[derive(Clone, Debug)]
+pub struct AttestationCache {
+	config: AttestationCacheConfig,
+	entries: Arc<Mutex<HashMap<Hash, CachedAttestation>>>,
+	database: Option<Database>,
+}
+
+impl AttestationCache {
+	/// `watched` tells whether a revocation watch invalidates the entries,
+	/// without one the ttl is capped at `MAX_UNWATCHED_TTL_SECS`
+	pub fn new(mut config: AttestationCacheConfig, database: &Database, watched: bool) -> Self {
+		if !watched && config.ttl_secs > MAX_UNWATCHED_TTL_SECS {
+			log::warn!(
+				target: KILT_LOG_TARGET,
+				"attestation cache ttl {}s capped at {}s, set kilt ws_url to watch revocations",
+				config.ttl_secs,
+				MAX_UNWATCHED_TTL_SECS
+			);
+			config.ttl_secs = MAX_UNWATCHED_TTL_SECS;
+		}
+		let database = if config.persistent { Some(database.clone()) } else { None };
+		AttestationCache { config, entries: Default::default(), database }
+	}
+
+	pub fn get(&self, root_hash: &Hash) -> Option<CachedAttestation> {
+		if self.config.ttl_secs == 0 {
+			return None
+		}
+		let now = now();
+		let mut entries = self.entries.lock().expect("attestation cache poisoned");
+		if let Some(entry) = entries.get(root_hash) {
+			if self.is_fresh(entry, now) {
+				return Some(entry.clone())
+			}
+			entries.remove(root_hash);
+		}
+
+		// load from the database after a restart
+		let entry = match self.database.as_ref().map(|db| db.cached_attestation(root_hash)) {
+			Some(Ok(Some(entry))) => entry,
+			Some(Err(e)) => {
+				log::warn!(target: KILT_LOG_TARGET, "attestation cache read: {:?}", e);
+				return None
+			},
+			_ => return None,
+		};
+		if self.is_fresh(&entry, now) {
+			entries.insert(*root_hash, entry.clone());
+			Some(entry)
+		} else {
+			None
+		}
+	}
+
+	pub fn insert(&self, root_hash: Hash, attestation: Option<Attestation>, at: Hash) {
+		if self.config.ttl_secs == 0 {
+			return
+		}
+		let entry = CachedAttestation { attestation, at, cached_at: now() };
+		if let Some(db) = &self.database {
+			if let Err(e) = db.insert_cached_attestation(&root_hash, &entry) {
+				log::warn!(target: KILT_LOG_TARGET, "attestation cache write: {:?}", e);
+			}
+		}
+		self.entries
+			.lock()
+			.expect("attestation cache poisoned")
+			.insert(root_hash, entry);
+	}
+
+	/// root hashes of entries that may still be served, the revocation watch invalidates them
+	pub fn root_hashes(&self) -> BTreeSet<Hash> {
+		if self.config.ttl_secs == 0 {
+			return BTreeSet::new()
+		}
+		let now = now();
+		let mut root_hashes: BTreeSet<Hash> = self
+			.entries
+			.lock()
+			.expect("attestation cache poisoned")
+			.iter()
+			.filter(|(_, entry)| self.is_fresh(entry, now))
+			.map(|(root_hash, _)| *root_hash)
+			.collect();
+		// entries persisted by an earlier run are loaded on their first lookup
+		match self.database.as_ref().map(|db| db.cached_attestations()) {
+			Some(Ok(entries)) => root_hashes.extend(
+				entries
+					.into_iter()
+					.filter(|(_, entry)| self.is_fresh(entry, now))
+					.map(|(root_hash, _)| root_hash),
+			),
+			Some(Err(e)) => log::warn!(target: KILT_LOG_TARGET, "attestation cache read: {:?}", e),
+			None => {},
+		}
+		root_hashes
+	}
+
+	/// drop the entry before it expires, e.g. once a revocation is observed
+	pub fn invalidate(&self, root_hash: &Hash) {
+		self.entries.lock().expect("attestation cache poisoned").remove(root_hash);
+		if let Some(db) = &self.database {
+			if let Err(e) = db.remove_cached_attestation(root_hash) {
+				log::warn!(target: KILT_LOG_TARGET, "attestation cache remove: {:?}", e);
+			}
+		}
+	}
+
+	fn is_fresh(&self, entry: &CachedAttestation, now: u64) -> bool {
+		let ttl = match entry.attestation {
+			Some(_) => self.config.ttl_secs,
+			None => self.config.negative_ttl_secs.min(self.config.ttl_secs),
+		};
+		now < entry.cached_at.saturating_add(ttl)
+	}
+}
+
+fn now() -> u64 {
+	SystemTime::now()
+		.duration_since(UNIX_EPOCH)
+		.map(|d| d.as_secs())
+		.unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{
+		AttestationCache, AttestationCacheConfig, CachedAttestation, MAX_UNWATCHED_TTL_SECS,
+	};
+	use crate::{kilt::Attestation, Database, Hash};
+
+	#[test]
+	fn negative_entries_should_expire_first() {
+		let db = Database::temporary().expect("fail to open temporary database");
+		let cache = AttestationCache::new(
+			AttestationCacheConfig { ttl_secs: 100, negative_ttl_secs: 10, persistent: false },
+			&db,
+			true,
+		);
+		let positive = CachedAttestation {
+			attestation: Some(Attestation::default()),
+			at: Hash::zero(),
+			cached_at: 1000,
+		};
+		let negative = CachedAttestation { attestation: None, ..positive.clone() };
+
+		assert!(cache.is_fresh(&positive, 1050));
+		assert!(!cache.is_fresh(&negative, 1050));
+		assert!(cache.is_fresh(&negative, 1009));
+		assert!(!cache.is_fresh(&positive, 1100));
+	}
+
+	#[test]
+	fn cache_should_be_invalidated() {
+		let db = Database::temporary().expect("fail to open temporary database");
+		let config =
+			AttestationCacheConfig { ttl_secs: 600, persistent: true, ..Default::default() };
+		let cache = AttestationCache::new(config.clone(), &db, true);
+		let root_hash = Hash::repeat_byte(1);
+
+		cache.insert(root_hash, Some(Attestation::default()), Hash::repeat_byte(2));
+		assert_eq!(cache.get(&root_hash).unwrap().at, Hash::repeat_byte(2));
+		assert!(cache.root_hashes().contains(&root_hash));
+
+		// survives a restart through the database
+		let restarted = AttestationCache::new(config.clone(), &db, true);
+		assert!(restarted.get(&root_hash).is_some());
+
+		restarted.invalidate(&root_hash);
+		assert!(restarted.get(&root_hash).is_none());
+		assert!(restarted.root_hashes().is_empty());
+		assert!(AttestationCache::new(config, &db, true).get(&root_hash).is_none());
+	}
+
+	#[test]
+	fn unwatched_cache_should_cap_ttl() {
+		let db = Database::temporary().expect("fail to open temporary database");
+		let config = AttestationCacheConfig { ttl_secs: 600, ..Default::default() };
+		assert_eq!(AttestationCache::new(config.clone(), &db, true).config.ttl_secs, 600);
+		assert_eq!(
+			AttestationCache::new(config, &db, false).config.ttl_secs,
+			MAX_UNWATCHED_TTL_SECS
+		);
+		assert_eq!(AttestationCacheConfig::default().ttl_secs, 0);
+	}
+}
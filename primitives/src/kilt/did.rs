@@ -0,0 +1,125 @@
+use std::fmt;
+
+use codec::Decode;
+use sp_core::crypto::{Ss58AddressFormat, Ss58Codec};
+
+use super::{
+	AccountId, Deserialize, Result, Serialize, StorageData, StorageEntry, StorageKey, HASHER,
+};
+
+/// ss58 prefix of KILT addresses, used in `did:kilt:` identifiers
+pub const KILT_SS58_PREFIX: u16 = 38;
+
+pub const DIDS: StorageEntry = StorageEntry::new("Did", "Did");
+pub const DID_BLACKLIST: StorageEntry = StorageEntry::new("Did", "DidBlacklist");
+pub const WEB3_NAMES: StorageEntry = StorageEntry::new("Web3Names", "Names");
+
+/// `did:kilt:<ss58 address>` of a DID identifier
+pub fn did_uri(identifier: &AccountId) -> String {
+	format!(
+		"did:kilt:{}",
+		identifier.to_ss58check_with_version(Ss58AddressFormat::custom(KILT_SS58_PREFIX))
+	)
+}
+
+/// storage keys read to resolve a DID: details, blacklist and web3 name
+pub fn did_storage_keys(identifier: &AccountId) -> Vec<StorageKey> {
+	vec![
+		DIDS.map_key(identifier, &HASHER),
+		DID_BLACKLIST.map_key(identifier, &HASHER),
+		WEB3_NAMES.map_key(identifier, &HASHER),
+	]
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DidStatus {
+	Active,
+	/// deleted by its owner, the identifier can never be used again
+	Deleted,
+	/// never created on chain
+	Missing,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DidIdentity {
+	pub did: String,
+	pub status: DidStatus,
+	pub web3_name: Option<String>,
+}
+
+impl DidIdentity {
+	/// build from values of `did_storage_keys`, in the same order
+	pub fn from_storages(
+		identifier: &AccountId,
+		values: Vec<Result<Option<StorageData>>>,
+	) -> Result<Self> {
+		let mut values = values.into_iter();
+		let mut next = || values.next().transpose().map(Option::flatten);
+		let (details, blacklisted, web3_name) = (next()?, next()?, next()?);
+		let status = match (details, blacklisted) {
+			(_, Some(_)) => DidStatus::Deleted,
+			(Some(_), None) => DidStatus::Active,
+			(None, None) => DidStatus::Missing,
+		};
+		// web3 names are bounded vectors of ascii bytes
+		let web3_name = match web3_name {
+			Some(data) => {
+				let name: Vec<u8> = Decode::decode(&mut data.0.as_slice())?;
+				Some(String::from_utf8_lossy(&name).into_owned())
+			},
+			None => None,
+		};
+		Ok(DidIdentity { did: did_uri(identifier), status, web3_name })
+	}
+
+	pub fn is_active(&self) -> bool {
+		self.status == DidStatus::Active
+	}
+}
+
+impl fmt::Display for DidIdentity {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match &self.web3_name {
+			Some(name) => write!(f, "{}(w3n:{}, {:?})", self.did, name, self.status),
+			None => write!(f, "{}({:?})", self.did, self.status),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{did_uri, DidIdentity, DidStatus};
+	use crate::{kilt::AccountId, StorageData};
+	use codec::Encode;
+
+	#[test]
+	fn did_uri_should_use_kilt_prefix() {
+		let did = did_uri(&AccountId::new([0u8; 32]));
+		assert!(did.starts_with("did:kilt:4"));
+	}
+
+	#[test]
+	fn did_status_should_work() {
+		let identifier = AccountId::new([1u8; 32]);
+		let present = StorageData(vec![]);
+		let name = StorageData(b"zcloak".to_vec().encode());
+
+		let active = DidIdentity::from_storages(
+			&identifier,
+			vec![Ok(Some(present.clone())), Ok(None), Ok(Some(name))],
+		)
+		.unwrap();
+		assert!(active.is_active());
+		assert_eq!(active.web3_name, Some("zcloak".to_string()));
+
+		let deleted =
+			DidIdentity::from_storages(&identifier, vec![Ok(None), Ok(Some(present)), Ok(None)])
+				.unwrap();
+		assert_eq!(deleted.status, DidStatus::Deleted);
+		assert!(!deleted.is_active());
+
+		let missing =
+			DidIdentity::from_storages(&identifier, vec![Ok(None), Ok(None), Ok(None)]).unwrap();
+		assert_eq!(missing.status, DidStatus::Missing);
+	}
+}
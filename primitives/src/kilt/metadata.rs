@@ -0,0 +1,242 @@
+use codec::Decode;
+use frame_metadata::{v14::StorageEntryType, RuntimeMetadata, RuntimeMetadataPrefixed};
+use scale_info::{meta_type, PortableRegistry, Registry, TypeDef, TypeInfo};
+
+use super::{
+	AccountId, Attestation, Deposit, Deserialize, Error, Hash, Result, Serialize, ATTESTATIONS,
+	HASHER,
+};
+
+/// response of `state_getRuntimeVersion`, only the fields the keeper logs
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RuntimeVersion {
+	pub spec_name: String,
+	pub spec_version: u32,
+}
+
+/// layouts of `Attestation::Attestations` values the keeper can decode
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AttestationLayout {
+	/// before storage deposits were introduced
+	Legacy,
+	/// with the storage deposit of the attester
+	WithDeposit,
+}
+
+const LEGACY_FIELDS: [&str; 4] = ["ctype_hash", "attester", "delegation_id", "revoked"];
+const WITH_DEPOSIT_FIELDS: [&str; 5] =
+	["ctype_hash", "attester", "delegation_id", "revoked", "deposit"];
+
+// nested types resolved when comparing encodings, runtime types are far shallower
+const MAX_TYPE_DEPTH: usize = 16;
+
+#[derive(Decode, TypeInfo)]
+struct LegacyAttestationDetails {
+	ctype_hash: Hash,
+	attester: AccountId,
+	delegation_id: Option<Hash>,
+	revoked: bool,
+}
+
+impl AttestationLayout {
+	fn from_fields(fields: &[&str]) -> Option<Self> {
+		if fields == LEGACY_FIELDS {
+			Some(AttestationLayout::Legacy)
+		} else if fields == WITH_DEPOSIT_FIELDS {
+			Some(AttestationLayout::WithDeposit)
+		} else {
+			None
+		}
+	}
+
+	// encoding of the type values of the layout are decoded into
+	fn encoding(&self) -> Option<String> {
+		let mut registry = Registry::new();
+		let ty = match self {
+			AttestationLayout::Legacy =>
+				registry.register_type(&meta_type::<LegacyAttestationDetails>()),
+			AttestationLayout::WithDeposit => registry.register_type(&meta_type::<Attestation>()),
+		};
+		encoding(&PortableRegistry::from(registry), ty.id(), 0)
+	}
+
+	pub fn decode(&self, mut data: &[u8]) -> Result<Attestation> {
+		match self {
+			AttestationLayout::WithDeposit => Ok(Attestation::decode(&mut data)?),
+			AttestationLayout::Legacy => {
+				let legacy = LegacyAttestationDetails::decode(&mut data)?;
+				Ok(Attestation {
+					ctype_hash: legacy.ctype_hash,
+					attester: legacy.attester.clone(),
+					delegation_id: legacy.delegation_id,
+					revoked: legacy.revoked,
+					deposit: Deposit { owner: legacy.attester, amount: 0 },
+				})
+			},
+		}
+	}
+}
+
+/// find the layout of `Attestation::Attestations` in the SCALE encoded runtime metadata
+pub fn attestation_layout(metadata: &[u8]) -> Result<AttestationLayout> {
+	let prefixed = RuntimeMetadataPrefixed::decode(&mut &metadata[..])?;
+	let metadata = match prefixed.1 {
+		RuntimeMetadata::V14(m) => m,
+		_ => return Err(Error::UnsupportedMetadata("only metadata V14 is supported".to_owned())),
+	};
+
+	let entry = metadata
+		.pallets
+		.iter()
+		.find(|p| p.name == ATTESTATIONS.pallet)
+		.and_then(|p| p.storage.as_ref())
+		.and_then(|s| s.entries.iter().find(|e| e.name == ATTESTATIONS.name))
+		.ok_or_else(|| {
+			Error::UnsupportedMetadata(format!(
+				"storage {}::{} not found",
+				ATTESTATIONS.pallet, ATTESTATIONS.name
+			))
+		})?;
+
+	let value_type = match &entry.ty {
+		StorageEntryType::Map { hashers, value, .. } if hashers.as_slice() == [HASHER] =>
+			value.id(),
+		other =>
+			return Err(Error::UnsupportedStorageLayout(format!(
+				"unexpected storage entry type {:?}",
+				other
+			))),
+	};
+
+	layout_of(&metadata.types, value_type)
+}
+
+// the layout whose fields have the names and the encodings of the type `id`
+fn layout_of(types: &PortableRegistry, id: u32) -> Result<AttestationLayout> {
+	let fields = match types.resolve(id).map(|t| t.type_def()) {
+		Some(TypeDef::Composite(composite)) => composite
+			.fields()
+			.iter()
+			.map(|f| f.name().map(|n| n.as_str()).unwrap_or_default())
+			.collect::<Vec<_>>(),
+		other =>
+			return Err(Error::UnsupportedStorageLayout(format!(
+				"unexpected attestation type {:?}",
+				other
+			))),
+	};
+	let layout = AttestationLayout::from_fields(&fields).ok_or_else(|| {
+		Error::UnsupportedStorageLayout(format!("unknown attestation fields {:?}", fields))
+	})?;
+
+	// a field may keep its name while its type changes
+	let (found, expected) = (encoding(types, id, 0), layout.encoding());
+	if found.is_none() || found != expected {
+		return Err(Error::UnsupportedStorageLayout(format!(
+			"attestation fields {:?} are encoded as {:?}, expected {:?}",
+			fields, found, expected
+		)))
+	}
+	Ok(layout)
+}
+
+// how values of the type `id` are SCALE encoded, names and paths left out,
+// `None` if a type can not be resolved
+fn encoding(types: &PortableRegistry, id: u32, depth: usize) -> Option<String> {
+	if depth > MAX_TYPE_DEPTH {
+		return None
+	}
+	let nested = |id: u32| encoding(types, id, depth + 1);
+	let joined = |ids: Vec<u32>| {
+		let encodings = ids.into_iter().map(nested).collect::<Option<Vec<_>>>()?;
+		Some(encodings.join(", "))
+	};
+
+	let encoding = match types.resolve(id)?.type_def() {
+		TypeDef::Composite(c) =>
+			format!("({})", joined(c.fields().iter().map(|f| f.ty().id()).collect())?),
+		TypeDef::Variant(v) => {
+			let variants = v
+				.variants()
+				.iter()
+				.map(|v| {
+					let fields = joined(v.fields().iter().map(|f| f.ty().id()).collect())?;
+					Some(format!("{}({})", v.index(), fields))
+				})
+				.collect::<Option<Vec<_>>>()?;
+			format!("enum {{{}}}", variants.join(", "))
+		},
+		TypeDef::Sequence(s) => format!("Vec<{}>", nested(s.type_param().id())?),
+		TypeDef::Array(a) => format!("[{}; {}]", nested(a.type_param().id())?, a.len()),
+		TypeDef::Tuple(t) => format!("({})", joined(t.fields().iter().map(|f| f.id()).collect())?),
+		TypeDef::Primitive(p) => format!("{:?}", p),
+		TypeDef::Compact(c) => format!("Compact<{}>", nested(c.type_param().id())?),
+		TypeDef::BitSequence(_) => "BitVec".to_owned(),
+	};
+	Some(encoding)
+}
+
+#[cfg(test)]
+mod tests {
+	use codec::Encode;
+	use scale_info::{meta_type, PortableRegistry, Registry, TypeInfo};
+
+	use super::{
+		layout_of, AttestationLayout, LegacyAttestationDetails, LEGACY_FIELDS, WITH_DEPOSIT_FIELDS,
+	};
+	use crate::kilt::{AccountId, Attestation, Deposit, Hash};
+
+	// same field names as the legacy layout, `revoked` is no longer a bool
+	#[allow(dead_code)]
+	#[derive(TypeInfo)]
+	struct RetypedAttestationDetails {
+		ctype_hash: Hash,
+		attester: AccountId,
+		delegation_id: Option<Hash>,
+		revoked: u8,
+	}
+
+	#[test]
+	fn attestation_layout_from_fields_should_work() {
+		assert_eq!(AttestationLayout::from_fields(&LEGACY_FIELDS), Some(AttestationLayout::Legacy));
+		assert_eq!(
+			AttestationLayout::from_fields(&WITH_DEPOSIT_FIELDS),
+			Some(AttestationLayout::WithDeposit)
+		);
+		assert_eq!(AttestationLayout::from_fields(&["ctype_hash", "attester"]), None);
+	}
+
+	#[test]
+	fn attestation_layout_should_check_field_types() {
+		let mut registry = Registry::new();
+		let legacy = registry.register_type(&meta_type::<LegacyAttestationDetails>()).id();
+		let current = registry.register_type(&meta_type::<Attestation>()).id();
+		let retyped = registry.register_type(&meta_type::<RetypedAttestationDetails>()).id();
+		let types = PortableRegistry::from(registry);
+
+		assert_eq!(layout_of(&types, legacy).unwrap(), AttestationLayout::Legacy);
+		assert_eq!(layout_of(&types, current).unwrap(), AttestationLayout::WithDeposit);
+		assert!(layout_of(&types, retyped).is_err());
+	}
+
+	#[test]
+	fn legacy_attestation_decode_should_work() {
+		let attester = AccountId::new([2u8; 32]);
+		let encoded =
+			(Hash::repeat_byte(1), attester.clone(), Some(Hash::repeat_byte(3)), true).encode();
+		let attestation = AttestationLayout::Legacy.decode(&encoded).unwrap();
+		assert_eq!(
+			attestation,
+			Attestation {
+				ctype_hash: Hash::repeat_byte(1),
+				attester: attester.clone(),
+				delegation_id: Some(Hash::repeat_byte(3)),
+				revoked: true,
+				deposit: Deposit { owner: attester, amount: 0 },
+			}
+		);
+		// the same bytes are too short for the current layout
+		assert!(AttestationLayout::WithDeposit.decode(&encoded).is_err());
+	}
+}
@@ -0,0 +1,435 @@
+use super::{Deserialize, Serialize, *};
+pub use cache::{AttestationCache, AttestationCacheConfig, CachedAttestation};
+use codec::{Decode, Encode};
+pub use did::{
+	did_storage_keys, did_uri, DidIdentity, DidStatus, DIDS, DID_BLACKLIST, KILT_SS58_PREFIX,
+	WEB3_NAMES,
+};
+use jsonrpsee::{
+	http_client::{HttpClient, HttpClientBuilder},
+	types::{to_json_value, traits::Client, Error as RpcError},
+};
+pub use metadata::{attestation_layout, AttestationLayout, RuntimeVersion};
+pub use proof::{
+	read_proof_value, read_proof_values, AuthorityId, AuthorityList, BlockNumber, Checkpoint,
+	FinalityProof, Header, ReadProof, TrustedHeader,
+};
+use scale_info::TypeInfo;
+pub use sp_runtime::AccountId32 as AccountId;
+use std::collections::BTreeSet;
+pub use storage::{
+	hash_encoded_key, key_hash, StorageEntry, StorageHasher, ATTESTATIONS, DELEGATION_NODES,
+	KEYS_PAGE_SIZE,
+};
+pub use subscribe::KiltSubscriber;
+
+mod cache;
+mod did;
+mod metadata;
+mod proof;
+mod storage;
+mod subscribe;
+
+pub const KILT_LOG_TARGET: &str = "KILT";
+const HASHER: StorageHasher = StorageHasher::Blake2_128Concat;
+
+//fixme: make generic
+pub type Balance = u128;
+
+#[derive(Default, Clone, Debug, Encode, Decode, TypeInfo, PartialEq, Serialize, Deserialize)]
+pub struct Deposit<Account, Balance> {
+	pub owner: Account,
+	pub amount: Balance,
+}
+
+#[derive(Default, Clone, Debug, Encode, Decode, TypeInfo, PartialEq, Serialize, Deserialize)]
+pub struct AttestationDetails<Hash: Encode + Clone, Account, Balance> {
+	pub ctype_hash: Hash,
+	pub attester: Account,
+	pub delegation_id: Option<Hash>,
+	pub revoked: bool,
+	pub deposit: Deposit<Account, Balance>,
+}
+
+pub type Attestation = AttestationDetails<Hash, AccountId, Balance>;
+
+#[derive(Default, Clone, Debug, Encode, Decode, PartialEq, Serialize, Deserialize)]
+pub struct DelegationDetails<Account> {
+	pub owner: Account,
+	pub revoked: bool,
+	// bitflags of attest(0b1) and delegate(0b10)
+	pub permissions: u32,
+}
+
+#[derive(Default, Clone, Debug, Encode, Decode, PartialEq, Serialize, Deserialize)]
+pub struct DelegationNodeDetails<Hash: Encode + Clone + Ord, Account, Balance> {
+	pub hierarchy_root_id: Hash,
+	pub parent: Option<Hash>,
+	pub children: BTreeSet<Hash>,
+	pub details: DelegationDetails<Account>,
+	pub deposit: Deposit<Account, Balance>,
+}
+
+pub type DelegationNode = DelegationNodeDetails<Hash, AccountId, Balance>;
+
+#[derive(Eq, PartialEq, Clone, Debug, Deserialize, Serialize)]
+pub struct TrustedAttesters {
+	pub ctype_hash: Hash,
+	pub attesters: Vec<AccountId>,
+}
+
+/// which credentials the keeper trusts, every direct attestation is trusted by default
+#[derive(Eq, PartialEq, Clone, Debug, Deserialize, Serialize)]
+pub struct TrustPolicy {
+	// allowlist of attesters per ctype, no restriction if empty
+	#[serde(default)]
+	pub trusted_attesters: Vec<TrustedAttesters>,
+	// accept attestations created through a delegation, anyone can create a hierarchy
+	// so it is off by default
+	#[serde(default)]
+	pub accept_delegations: bool,
+	// hierarchy roots a delegated attestation must descend from, any root if empty
+	#[serde(default)]
+	pub trusted_delegation_roots: Vec<Hash>,
+}
+
+impl Default for TrustPolicy {
+	fn default() -> Self {
+		TrustPolicy {
+			trusted_attesters: vec![],
+			accept_delegations: false,
+			trusted_delegation_roots: vec![],
+		}
+	}
+}
+
+impl TrustPolicy {
+	pub fn is_attester_trusted(&self, ctype_hash: &Hash, attester: &AccountId) -> bool {
+		if self.trusted_attesters.is_empty() {
+			return true
+		}
+		self.trusted_attesters
+			.iter()
+			.any(|t| &t.ctype_hash == ctype_hash && t.attesters.contains(attester))
+	}
+
+	pub fn is_root_trusted(&self, root: &Hash) -> bool {
+		self.trusted_delegation_roots.is_empty() || self.trusted_delegation_roots.contains(root)
+	}
+
+	// the allowlist of the ctype holds either the attester or the owner of the hierarchy root
+	pub fn is_delegation_trusted(
+		&self,
+		ctype_hash: &Hash,
+		attester: &AccountId,
+		root: &Hash,
+		root_owner: &AccountId,
+	) -> bool {
+		self.accept_delegations &&
+			self.is_root_trusted(root) &&
+			(self.is_attester_trusted(ctype_hash, attester) ||
+				self.is_attester_trusted(ctype_hash, root_owner))
+	}
+}
+
+#[derive(Eq, PartialEq, Clone, Debug, Deserialize, Serialize)]
+pub struct KiltConfig {
+	pub url: String,
+	// websocket endpoint to watch approved attestations for revocation
+	#[serde(default)]
+	pub ws_url: Option<String>,
+	#[serde(default)]
+	pub trust: TrustPolicy,
+	#[serde(default)]
+	pub cache: AttestationCacheConfig,
+	// verify storage proofs against heads finalized by GRANDPA justifications, starting
+	// from this checkpoint and its authority set. plain storage queries are trusted if not set
+	#[serde(default)]
+	pub checkpoint: Option<Checkpoint>,
+	// double check ctype and attester with aggregator's `checkAttestation`
+	#[serde(default)]
+	pub check_on_chain: bool,
+	#[serde(default)]
+	pub retry: RetryPolicy,
+}
+
+#[derive(Clone, Debug)]
+pub struct KiltClient {
+	client: HttpClient,
+	trusted_header: Option<TrustedHeader>,
+	attestation_layout: AttestationLayout,
+	pub ip_address: String,
+	pub retry: RetryPolicy,
+	pub metrics: metrics::Metrics,
+}
+
+impl KiltClient {
+	// connect to kilt and check the runtime storage layout is supported
+	pub async fn try_from_url(url: &str) -> Result<Self> {
+		if url.starts_with("http://") || url.starts_with("https://") {
+			let client = HttpClientBuilder::default().build(&url)?;
+
+			let version: RuntimeVersion = client.request("state_getRuntimeVersion", None).await?;
+			let metadata: Bytes = client.request("state_getMetadata", None).await?;
+			let attestation_layout = attestation_layout(&metadata.0)?;
+			log::info!(
+				target: KILT_LOG_TARGET,
+				"kilt runtime {} v{} | attestation layout: {:?}",
+				version.spec_name,
+				version.spec_version,
+				attestation_layout
+			);
+
+			Ok(KiltClient {
+				client,
+				trusted_header: None,
+				attestation_layout,
+				ip_address: url.to_string(),
+				retry: Default::default(),
+				metrics: Default::default(),
+			})
+		} else {
+			Err(Error::UrlFormatError(
+				"Kilt client connection must start with http or https".to_owned(),
+			))
+		}
+	}
+
+	// read storage through verified proofs only, starting from the checkpoint
+	pub fn with_checkpoint(mut self, checkpoint: Option<Checkpoint>) -> Self {
+		self.trusted_header = checkpoint.map(TrustedHeader::new);
+		self
+	}
+
+	pub fn with_retry(mut self, retry: RetryPolicy) -> Self {
+		self.retry = retry;
+		self
+	}
+
+	pub fn with_metrics(mut self, metrics: metrics::Metrics) -> Self {
+		self.metrics = metrics;
+		self
+	}
+
+	pub fn verifies_proofs(&self) -> bool {
+		self.trusted_header.is_some()
+	}
+
+	// decode `Attestation::Attestations` value with the layout of the connected runtime
+	pub fn decode_attestation(&self, data: &StorageData) -> Result<Attestation> {
+		self.attestation_layout.decode(&data.0)
+	}
+
+	// latest finalized block hash, the latest one proved by a justification if a checkpoint
+	// is set
+	pub async fn finalized_head(&self) -> Result<Hash> {
+		match &self.trusted_header {
+			Some(trusted_header) =>
+				trusted_header
+					.verified_head(
+						|n| async move { self.prove_finality(n).await },
+						|h| async move { self.header(h).await },
+					)
+					.await,
+			None => Ok(self.client.request("chain_getFinalizedHead", None).await?),
+		}
+	}
+
+	// finality proof of the last block of the authority set `number` is in,
+	// or of the latest justified block in the current set
+	pub async fn prove_finality(&self, number: BlockNumber) -> Result<Option<FinalityProof>> {
+		let params = vec![to_json_value(number)?];
+		let maybe_proof: Option<Bytes> =
+			self.client.request("grandpa_proveFinality", Some(params.into())).await?;
+		maybe_proof
+			.map(|proof| {
+				FinalityProof::decode(&mut proof.0.as_slice()).map_err(|e| {
+					Error::HeaderChainError(format!("undecodable finality proof, err: {}", e))
+				})
+			})
+			.transpose()
+	}
+
+	// fetch storage
+	pub async fn request_storage(
+		&self,
+		key: &StorageKey,
+		hash: Option<Hash>,
+	) -> std::result::Result<Option<StorageData>, RpcError> {
+		let params = vec![to_json_value(key)?, to_json_value(hash)?];
+		let data = self.client.request("state_getStorage", Some(params.into())).await?;
+		Ok(data)
+	}
+
+	// fetch header by hash
+	pub async fn header(&self, hash: Hash) -> Result<Header> {
+		let params = vec![to_json_value(hash)?];
+		let maybe_header: Option<Header> =
+			self.client.request("chain_getHeader", Some(params.into())).await?;
+		maybe_header.ok_or_else(|| {
+			Error::HeaderChainError(format!("header {} not found", hex::encode(hash)))
+		})
+	}
+
+	// fetch storage proof of keys
+	pub async fn read_proof(
+		&self,
+		keys: &[StorageKey],
+		hash: Option<Hash>,
+	) -> std::result::Result<ReadProof, RpcError> {
+		let params = vec![to_json_value(keys)?, to_json_value(hash)?];
+		let proof = self.client.request("state_getReadProof", Some(params.into())).await?;
+		Ok(proof)
+	}
+
+	// fetch storage of many keys at the same block in one request
+	pub async fn query_storage_at(
+		&self,
+		keys: &[StorageKey],
+		hash: Option<Hash>,
+	) -> std::result::Result<Vec<StorageChangeSet<Hash>>, RpcError> {
+		let params = vec![to_json_value(keys)?, to_json_value(hash)?];
+		let change_sets = self.client.request("state_queryStorageAt", Some(params.into())).await?;
+		Ok(change_sets)
+	}
+
+	// fetch storage and check it against the state root of a head finalized by a verified
+	// justification, the latest one is used if `hash` is `None`
+	pub async fn request_verified_storage(
+		&self,
+		key: &StorageKey,
+		hash: Option<Hash>,
+	) -> Result<Option<StorageData>> {
+		let mut values = self.request_verified_storages(std::slice::from_ref(key), hash).await?;
+		values.pop().unwrap_or_else(|| {
+			Err(Error::StorageProofError("empty storage proof response".to_owned()))
+		})
+	}
+
+	// same as `request_verified_storage`, with a single proof covering all keys,
+	// the values are checked and returned per key in the same order
+	pub async fn request_verified_storages(
+		&self,
+		keys: &[StorageKey],
+		hash: Option<Hash>,
+	) -> Result<Vec<Result<Option<StorageData>>>> {
+		let trusted_header = self.trusted_header.as_ref().ok_or_else(|| {
+			Error::HeaderChainError("no checkpoint configured for kilt client".to_owned())
+		})?;
+		let at = match hash {
+			Some(h) => h,
+			None => self.finalized_head().await?,
+		};
+		let state_root = trusted_header.state_root(at).await?;
+
+		let read_proof = self.read_proof(keys, Some(at)).await?;
+		let raw_keys = keys.iter().map(|k| k.0.as_slice()).collect::<Vec<_>>();
+		let values = read_proof_values(&state_root, read_proof.proof, &raw_keys)
+			.into_iter()
+			.map(|v| v.map(|maybe_value| maybe_value.map(StorageData)))
+			.collect();
+		Ok(values)
+	}
+}
+
+/// get the storage key of attestations
+pub fn get_attestation_storage_key<Key: Encode>(key: Key) -> StorageKey {
+	ATTESTATIONS.map_key(&key, &HASHER)
+}
+
+/// get the storage key of delegation nodes
+pub fn get_delegation_node_storage_key<Key: Encode>(key: Key) -> StorageKey {
+	DELEGATION_NODES.map_key(&key, &HASHER)
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+	#[error("Wrong url initializing client connection, err: {0}")]
+	UrlFormatError(String),
+	#[error("Kilt RPC Client Error, err: {0}")]
+	KiltClientError(#[from] RpcError),
+	/// Serde serialization error
+	#[error("Serde json error: {0}")]
+	Serialization(#[from] serde_json::error::Error),
+	#[error("Error decoding storage value: {0}")]
+	StorageValueDecode(#[from] codec::Error),
+	#[error("Storage value missing in response, key: {0}")]
+	StorageValueMissing(String),
+	#[error("Attestation revoked after a passing verdict was submitted, root hash: {0}")]
+	RevokedAfterApproval(String),
+	#[error("Kilt storage subscription closed")]
+	SubscriptionClosed,
+	#[error("Unsupported kilt runtime metadata: {0}")]
+	UnsupportedMetadata(String),
+	#[error("Unsupported kilt storage layout: {0}")]
+	UnsupportedStorageLayout(String),
+	#[error("Invalid kilt storage proof: {0}")]
+	StorageProofError(String),
+	#[error("Unverifiable kilt header chain: {0}")]
+	HeaderChainError(String),
+}
+
+// timeouts and dropped connections, the node may answer the next request
+impl Retryable for RpcError {
+	fn is_retryable(&self) -> bool {
+		matches!(self, RpcError::RequestTimeout | RpcError::Transport(_))
+	}
+}
+
+impl Retryable for Error {
+	fn is_retryable(&self) -> bool {
+		match self {
+			Error::KiltClientError(e) => e.is_retryable(),
+			_ => false,
+		}
+	}
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+#[cfg(test)]
+mod tests {
+	use super::{AccountId, Hash, TrustPolicy, TrustedAttesters};
+
+	#[test]
+	fn trust_policy_default_should_reject_delegations() {
+		let policy: TrustPolicy = serde_json::from_str("{}").unwrap();
+		assert_eq!(policy, TrustPolicy::default());
+		assert!(!policy.accept_delegations);
+		let (ctype, root) = (Hash::repeat_byte(1), Hash::repeat_byte(9));
+		let anyone = AccountId::new([3u8; 32]);
+		assert!(!policy.is_delegation_trusted(&ctype, &anyone, &root, &anyone));
+	}
+
+	#[test]
+	fn trust_policy_should_work() {
+		let ctype = Hash::repeat_byte(1);
+		let attester = AccountId::new([2u8; 32]);
+		let stranger = AccountId::new([3u8; 32]);
+
+		let open = TrustPolicy::default();
+		assert!(open.is_attester_trusted(&ctype, &stranger));
+		assert!(open.is_root_trusted(&Hash::repeat_byte(9)));
+
+		let strict = TrustPolicy {
+			trusted_attesters: vec![TrustedAttesters {
+				ctype_hash: ctype,
+				attesters: vec![attester.clone()],
+			}],
+			accept_delegations: true,
+			trusted_delegation_roots: vec![Hash::repeat_byte(8)],
+		};
+		assert!(strict.is_attester_trusted(&ctype, &attester));
+		assert!(!strict.is_attester_trusted(&ctype, &stranger));
+		assert!(!strict.is_attester_trusted(&Hash::repeat_byte(4), &attester));
+		assert!(strict.is_root_trusted(&Hash::repeat_byte(8)));
+		assert!(!strict.is_root_trusted(&Hash::repeat_byte(9)));
+
+		// a delegated attestation is checked against the allowlist too
+		let root = Hash::repeat_byte(8);
+		assert!(strict.is_delegation_trusted(&ctype, &stranger, &root, &attester));
+		assert!(strict.is_delegation_trusted(&ctype, &attester, &root, &stranger));
+		assert!(!strict.is_delegation_trusted(&ctype, &stranger, &root, &stranger));
+		assert!(!strict.is_delegation_trusted(&ctype, &attester, &Hash::repeat_byte(9), &attester));
+	}
+}
@@ -0,0 +1,462 @@
+use std::{
+	collections::{HashMap, HashSet, VecDeque},
+	sync::Arc,
+};
+
+use codec::{Decode, Encode};
+use sp_core::{ed25519, Pair};
+use sp_runtime::{
+	generic,
+	traits::{BlakeTwo256, Header as _},
+	ConsensusEngineId,
+};
+use sp_trie::{read_trie_value, Layout, StorageProof};
+use tokio::sync::Mutex;
+
+use super::{Bytes, Deserialize, Error, Hash, Result, Serialize, KILT_LOG_TARGET};
+
+pub type BlockNumber = u64;
+pub type Header = generic::Header<BlockNumber, BlakeTwo256>;
+pub type AuthorityId = ed25519::Public;
+pub type AuthorityList = Vec<(AuthorityId, u64)>;
+
+const GRANDPA_ENGINE_ID: ConsensusEngineId = *b"FRNK";
+// index of `Precommit` in the GRANDPA `Message` enum, part of the signed payload
+const PRECOMMIT: u8 = 1;
+// finality proofs checked in one call, each one moves past an authority set change
+const MAX_FINALITY_STEPS: usize = 8;
+// verified heads whose state roots are kept, queries of a batch are pinned to one of them
+const VERIFIED_HEADS: usize = 16;
+
+/// response of `state_getReadProof`
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReadProof {
+	pub at: Hash,
+	pub proof: Vec<Bytes>,
+}
+
+/// a finalized kilt header the keeper trusts without asking any node, with the GRANDPA
+/// authority set finalizing the blocks after it
+#[derive(Eq, PartialEq, Clone, Debug, Deserialize, Serialize)]
+pub struct Checkpoint {
+	pub number: BlockNumber,
+	pub hash: Hash,
+	pub set_id: u64,
+	pub authorities: AuthorityList,
+}
+
+/// decoded response of `grandpa_proveFinality`
+#[derive(Clone, Debug, Decode)]
+pub struct FinalityProof {
+	pub block: Hash,
+	pub justification: Vec<u8>,
+	pub unknown_headers: Vec<Header>,
+}
+
+#[derive(Clone, Debug, Encode, Decode)]
+struct Precommit {
+	target_hash: Hash,
+	target_number: BlockNumber,
+}
+
+#[derive(Clone, Debug, Encode, Decode)]
+struct SignedPrecommit {
+	precommit: Precommit,
+	signature: ed25519::Signature,
+	id: AuthorityId,
+}
+
+#[derive(Clone, Debug, Encode, Decode)]
+struct Commit {
+	target_hash: Hash,
+	target_number: BlockNumber,
+	precommits: Vec<SignedPrecommit>,
+}
+
+#[derive(Clone, Debug, Encode, Decode)]
+struct GrandpaJustification {
+	round: u64,
+	commit: Commit,
+	votes_ancestries: Vec<Header>,
+}
+
+#[derive(Clone, Debug, Encode, Decode)]
+struct ScheduledChange {
+	next_authorities: AuthorityList,
+	delay: BlockNumber,
+}
+
+// GRANDPA logs in header digests, only set changes matter here
+#[derive(Clone, Debug, Encode, Decode)]
+enum ConsensusLog {
+	#[codec(index = 1)]
+	ScheduledChange(ScheduledChange),
+	#[codec(index = 2)]
+	ForcedChange(BlockNumber, ScheduledChange),
+}
+
+impl GrandpaJustification {
+	// precommits of more than 2/3 of the set's weight for `header` or its descendants
+	fn verify(
+		&self,
+		header: &Header,
+		hash: Hash,
+		set_id: u64,
+		authorities: &AuthorityList,
+	) -> Result<()> {
+		if self.commit.target_hash != hash || self.commit.target_number != header.number {
+			return Err(Error::HeaderChainError(format!(
+				"justification targets #{} {}, expected #{} {}",
+				self.commit.target_number,
+				hex::encode(self.commit.target_hash),
+				header.number,
+				hex::encode(hash)
+			)))
+		}
+
+		let ancestry =
+			self.votes_ancestries.iter().map(|h| (h.hash(), h)).collect::<HashMap<_, _>>();
+		let mut signers = HashSet::new();
+		let mut weight = 0u64;
+		for signed in &self.commit.precommits {
+			let voter_weight = match authorities.iter().find(|(id, _)| id == &signed.id) {
+				Some((_, w)) => *w,
+				None => continue,
+			};
+			let payload = (PRECOMMIT, &signed.precommit, self.round, set_id).encode();
+			if self.descends(&ancestry, &signed.precommit) &&
+				ed25519::Pair::verify(&signed.signature, payload, &signed.id) &&
+				signers.insert(signed.id)
+			{
+				weight += voter_weight;
+			}
+		}
+
+		let total = authorities.iter().map(|(_, w)| w).sum::<u64>();
+		let threshold = total - total.saturating_sub(1) / 3;
+		if total == 0 || weight < threshold {
+			return Err(Error::HeaderChainError(format!(
+				"justification of #{} has weight {} of set #{}, {} required",
+				header.number, weight, set_id, threshold
+			)))
+		}
+		Ok(())
+	}
+
+	// a precommit for a later block counts if the ancestries link it to the commit target
+	fn descends(&self, ancestry: &HashMap<Hash, &Header>, precommit: &Precommit) -> bool {
+		let (mut hash, mut number) = (precommit.target_hash, precommit.target_number);
+		while number > self.commit.target_number {
+			match ancestry.get(&hash) {
+				Some(h) if h.number == number => (hash, number) = (h.parent_hash, number - 1),
+				_ => return false,
+			}
+		}
+		(hash, number) == (self.commit.target_hash, self.commit.target_number)
+	}
+}
+
+// the next authority set enacted by a justified header
+fn authority_set_change(header: &Header) -> Result<Option<AuthorityList>> {
+	let log = header
+		.digest
+		.logs()
+		.iter()
+		.find_map(|log| log.consensus_try_to::<ConsensusLog>(&GRANDPA_ENGINE_ID));
+	match log {
+		Some(ConsensusLog::ScheduledChange(change)) if change.delay == 0 =>
+			Ok(Some(change.next_authorities)),
+		Some(ConsensusLog::ScheduledChange(change)) => Err(Error::HeaderChainError(format!(
+			"authority set change of #{} is delayed by {} blocks, which is not supported",
+			header.number, change.delay
+		))),
+		Some(ConsensusLog::ForcedChange(median, _)) => Err(Error::HeaderChainError(format!(
+			"forced authority set change of #{} after #{}, configure a newer checkpoint",
+			header.number, median
+		))),
+		None => Ok(None),
+	}
+}
+
+#[derive(Debug)]
+struct TrustedState {
+	checkpoint: Checkpoint,
+	// block hash and state root of verified heads, newest last
+	verified: VecDeque<(Hash, Hash)>,
+}
+
+impl TrustedState {
+	fn advance(&mut self, hash: Hash, header: &Header, set_change: Option<AuthorityList>) {
+		self.checkpoint.number = header.number;
+		self.checkpoint.hash = hash;
+		if let Some(authorities) = set_change {
+			self.checkpoint.set_id += 1;
+			self.checkpoint.authorities = authorities;
+		}
+		self.verified.push_back((hash, header.state_root));
+		if self.verified.len() > VERIFIED_HEADS {
+			self.verified.pop_front();
+		}
+	}
+}
+
+/// latest header finalized by GRANDPA, moves forward every time a justification of a newer
+/// header is signed by the authority set of the trusted one
+#[derive(Clone, Debug)]
+pub struct TrustedHeader {
+	inner: Arc<Mutex<TrustedState>>,
+}
+
+impl TrustedHeader {
+	pub fn new(checkpoint: Checkpoint) -> Self {
+		let state = TrustedState { checkpoint, verified: VecDeque::new() };
+		TrustedHeader { inner: Arc::new(Mutex::new(state)) }
+	}
+
+	/// hash of the latest head proved final. `fetch_proof` is asked for the finality proof
+	/// of the block after the trusted header, and its justification is checked against the
+	/// signatures of the trusted authority set. headers are re-hashed locally, so a
+	/// dishonest node can not make up a head without 2/3 of the authorities signing it.
+	/// justifications are not kept for every block, the head may lag behind the finalized one
+	pub async fn verified_head<P, PFut, H, HFut>(
+		&self,
+		fetch_proof: P,
+		fetch_header: H,
+	) -> Result<Hash>
+	where
+		P: Fn(BlockNumber) -> PFut,
+		PFut: std::future::Future<Output = Result<Option<FinalityProof>>>,
+		H: Fn(Hash) -> HFut,
+		HFut: std::future::Future<Output = Result<Header>>,
+	{
+		let mut state = self.inner.lock().await;
+		if state.verified.is_empty() {
+			// the checkpoint is trusted as configured, so is the header hashing to it
+			let hash = state.checkpoint.hash;
+			let header = fetch_header(hash).await?;
+			check_header_hash(&header, &hash)?;
+			state.verified.push_back((hash, header.state_root));
+		}
+
+		// one proof per authority set, the last block of a set is followed by the next set
+		for _ in 0..MAX_FINALITY_STEPS {
+			let proof = match fetch_proof(state.checkpoint.number + 1).await? {
+				Some(p) => p,
+				None => break,
+			};
+			let header = match proof.unknown_headers.iter().find(|h| h.hash() == proof.block) {
+				Some(h) => h.clone(),
+				None => fetch_header(proof.block).await?,
+			};
+			check_header_hash(&header, &proof.block)?;
+			if header.number <= state.checkpoint.number {
+				break
+			}
+			let justification = GrandpaJustification::decode(&mut proof.justification.as_slice())
+				.map_err(|e| {
+				Error::HeaderChainError(format!(
+					"undecodable justification of #{}, err: {}",
+					header.number, e
+				))
+			})?;
+			let (set_id, authorities) = (state.checkpoint.set_id, &state.checkpoint.authorities);
+			justification.verify(&header, proof.block, set_id, authorities)?;
+
+			let set_change = authority_set_change(&header)?;
+			let is_last_of_set = set_change.is_some();
+			state.advance(proof.block, &header, set_change);
+			log::info!(
+				target: KILT_LOG_TARGET,
+				"kilt header #{} {:} is finalized by authority set #{}",
+				header.number,
+				hex::encode(proof.block),
+				set_id
+			);
+			if !is_last_of_set {
+				break
+			}
+		}
+		Ok(state.checkpoint.hash)
+	}
+
+	/// state root of a head returned by `verified_head`, only the latest ones are kept
+	pub async fn state_root(&self, at: Hash) -> Result<Hash> {
+		let state = self.inner.lock().await;
+		let verified = state.verified.iter().rev().find(|(hash, _)| hash == &at);
+		verified.map(|(_, state_root)| *state_root).ok_or_else(|| {
+			Error::HeaderChainError(format!(
+				"kilt block {} is not among the verified heads",
+				hex::encode(at)
+			))
+		})
+	}
+}
+
+fn check_header_hash(header: &Header, expected: &Hash) -> Result<()> {
+	let hash = header.hash();
+	if &hash != expected {
+		return Err(Error::HeaderChainError(format!(
+			"header #{} hashes to {}, expected {}",
+			header.number,
+			hex::encode(hash),
+			hex::encode(expected)
+		)))
+	}
+	Ok(())
+}
+
+/// read the value of `key` out of a storage proof, `None` if the proof shows the key is absent
+pub fn read_proof_value(
+	state_root: &Hash,
+	proof: Vec<Bytes>,
+	key: &[u8],
+) -> Result<Option<Vec<u8>>> {
+	let mut values = read_proof_values(state_root, proof, &[key]);
+	values.pop().unwrap_or_else(|| Ok(None))
+}
+
+/// read the values of many keys out of one storage proof, checked separately per key
+pub fn read_proof_values(
+	state_root: &Hash,
+	proof: Vec<Bytes>,
+	keys: &[&[u8]],
+) -> Vec<Result<Option<Vec<u8>>>> {
+	let nodes = proof.into_iter().map(|b| b.0).collect::<Vec<_>>();
+	let db = StorageProof::new(nodes).into_memory_db::<BlakeTwo256>();
+	keys.iter()
+		.map(|key| {
+			read_trie_value::<Layout<BlakeTwo256>, _>(&db, state_root, key)
+				.map_err(|e| Error::StorageProofError(format!("{:?}", e)))
+		})
+		.collect()
+}
+
+#[cfg(test)]
+mod tests {
+	use codec::Encode;
+	use sp_core::{ed25519, Pair};
+	use sp_runtime::{
+		generic::{Digest, DigestItem},
+		traits::Header as _,
+	};
+
+	use super::{
+		AuthorityList, Checkpoint, Commit, ConsensusLog, FinalityProof, GrandpaJustification, Hash,
+		Header, Precommit, ScheduledChange, SignedPrecommit, TrustedHeader, GRANDPA_ENGINE_ID,
+		PRECOMMIT,
+	};
+	use crate::kilt::Error;
+
+	fn header(number: u64, parent_hash: Hash, set_change: Option<AuthorityList>) -> Header {
+		let mut digest = Digest { logs: vec![] };
+		if let Some(next_authorities) = set_change {
+			let log = ConsensusLog::ScheduledChange(ScheduledChange { next_authorities, delay: 0 });
+			digest.logs.push(DigestItem::Consensus(GRANDPA_ENGINE_ID, log.encode()));
+		}
+		Header::new(
+			number,
+			Hash::repeat_byte(1),
+			Hash::repeat_byte(number as u8),
+			parent_hash,
+			digest,
+		)
+	}
+
+	// finality proof of `header`, signed by `voters` in `set_id`
+	fn finality_proof(header: &Header, voters: &[ed25519::Pair], set_id: u64) -> FinalityProof {
+		let precommit = Precommit { target_hash: header.hash(), target_number: header.number };
+		let precommits = voters
+			.iter()
+			.map(|pair| {
+				let payload = (PRECOMMIT, &precommit, 1u64, set_id).encode();
+				SignedPrecommit {
+					precommit: precommit.clone(),
+					signature: pair.sign(&payload),
+					id: pair.public(),
+				}
+			})
+			.collect();
+		let commit =
+			Commit { target_hash: header.hash(), target_number: header.number, precommits };
+		let justification = GrandpaJustification { round: 1, commit, votes_ancestries: vec![] };
+		FinalityProof {
+			block: header.hash(),
+			justification: justification.encode(),
+			unknown_headers: vec![header.clone()],
+		}
+	}
+
+	fn authorities(pairs: &[ed25519::Pair]) -> AuthorityList {
+		pairs.iter().map(|p| (p.public(), 1)).collect()
+	}
+
+	#[tokio::test]
+	async fn justified_heads_should_follow_set_changes() {
+		let first_set = (0..4).map(|i| ed25519::Pair::from_seed(&[i; 32])).collect::<Vec<_>>();
+		let next_set = (4..8).map(|i| ed25519::Pair::from_seed(&[i; 32])).collect::<Vec<_>>();
+		let genesis = header(0, Hash::zero(), None);
+		// the last block of the first set enacts the next one
+		let set_end = header(5, Hash::repeat_byte(5), Some(authorities(&next_set)));
+		let latest = header(9, Hash::repeat_byte(9), None);
+		let proofs = vec![
+			(1, finality_proof(&set_end, &first_set[..3], 0)),
+			(6, finality_proof(&latest, &next_set, 1)),
+		];
+
+		let checkpoint = Checkpoint {
+			number: 0,
+			hash: genesis.hash(),
+			set_id: 0,
+			authorities: authorities(&first_set),
+		};
+		let trusted = TrustedHeader::new(checkpoint);
+		let fetch_proof = |number: u64| {
+			let found = proofs.iter().find(|(n, _)| *n == number).map(|(_, p)| p.clone());
+			async move { Ok::<_, Error>(found) }
+		};
+		let fetch_header = |hash: Hash| {
+			let found = (hash == genesis.hash()).then(|| genesis.clone());
+			async move { found.ok_or(Error::HeaderChainError("unknown header".to_owned())) }
+		};
+
+		let head = trusted.verified_head(fetch_proof, fetch_header).await.unwrap();
+		assert_eq!(head, latest.hash());
+		assert_eq!(trusted.state_root(head).await.unwrap(), latest.state_root);
+		assert_eq!(trusted.state_root(set_end.hash()).await.unwrap(), set_end.state_root);
+		assert!(trusted.state_root(Hash::repeat_byte(7)).await.is_err());
+	}
+
+	#[tokio::test]
+	async fn unsigned_heads_should_be_rejected() {
+		let voters = (0..4).map(|i| ed25519::Pair::from_seed(&[i; 32])).collect::<Vec<_>>();
+		let strangers = (4..8).map(|i| ed25519::Pair::from_seed(&[i; 32])).collect::<Vec<_>>();
+		let genesis = header(0, Hash::zero(), None);
+		let forged = header(3, Hash::repeat_byte(3), None);
+		let checkpoint = Checkpoint {
+			number: 0,
+			hash: genesis.hash(),
+			set_id: 0,
+			authorities: authorities(&voters),
+		};
+		let fetch_header = |_: Hash| {
+			let genesis = genesis.clone();
+			async move { Ok::<_, Error>(genesis) }
+		};
+
+		// 2 of 4 votes are below the threshold, signatures of other keys do not count
+		for proof in [
+			finality_proof(&forged, &voters[..2], 0),
+			finality_proof(&forged, &strangers, 0),
+			finality_proof(&forged, &voters, 1),
+		] {
+			let trusted = TrustedHeader::new(checkpoint.clone());
+			let fetch_proof = |_: u64| {
+				let proof = proof.clone();
+				async move { Ok::<_, Error>(Some(proof)) }
+			};
+			assert!(trusted.verified_head(fetch_proof, fetch_header).await.is_err());
+			assert!(trusted.state_root(forged.hash()).await.is_err());
+		}
+	}
+}
@@ -0,0 +1,237 @@
+use std::collections::HashMap;
+
+use codec::{Decode, Encode};
+pub use frame_metadata::StorageHasher;
+use jsonrpsee::types::{to_json_value, traits::Client};
+
+use super::{Error, Hash, KiltClient, Result, StorageData, StorageKey};
+
+// max keys fetched by one `state_getKeysPaged` request
+pub const KEYS_PAGE_SIZE: u32 = 512;
+
+pub const ATTESTATIONS: StorageEntry = StorageEntry::new("Attestation", "Attestations");
+pub const DELEGATION_NODES: StorageEntry = StorageEntry::new("Delegation", "DelegationNodes");
+
+/// a storage item of a runtime pallet, e.g. `Attestation::Attestations`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StorageEntry {
+	pub pallet: &'static str,
+	pub name: &'static str,
+}
+
+impl StorageEntry {
+	pub const fn new(pallet: &'static str, name: &'static str) -> Self {
+		StorageEntry { pallet, name }
+	}
+
+	/// twox_128(pallet) ++ twox_128(name), shared by all keys of the item
+	pub fn prefix(&self) -> StorageKey {
+		let mut bytes = sp_core::twox_128(self.pallet.as_bytes()).to_vec();
+		bytes.extend(&sp_core::twox_128(self.name.as_bytes())[..]);
+		StorageKey(bytes)
+	}
+
+	pub fn value_key(&self) -> StorageKey {
+		self.prefix()
+	}
+
+	pub fn map_key<K: Encode>(&self, key: &K, hasher: &StorageHasher) -> StorageKey {
+		self.n_map_key(&[(key.encode(), hasher.clone())])
+	}
+
+	pub fn double_map_key<K1: Encode, K2: Encode>(
+		&self,
+		key1: &K1,
+		hasher1: &StorageHasher,
+		key2: &K2,
+		hasher2: &StorageHasher,
+	) -> StorageKey {
+		self.n_map_key(&[(key1.encode(), hasher1.clone()), (key2.encode(), hasher2.clone())])
+	}
+
+	/// keys are SCALE encoded, leading keys only give the prefix to iterate the rest
+	pub fn n_map_key(&self, keys: &[(Vec<u8>, StorageHasher)]) -> StorageKey {
+		let mut key = self.prefix();
+		for (encoded, hasher) in keys {
+			key.0.extend(hash_encoded_key(encoded, hasher));
+		}
+		key
+	}
+}
+
+pub fn key_hash<K: Encode>(key: &K, hasher: &StorageHasher) -> Vec<u8> {
+	hash_encoded_key(&key.encode(), hasher)
+}
+
+pub fn hash_encoded_key(encoded_key: &[u8], hasher: &StorageHasher) -> Vec<u8> {
+	match hasher {
+		StorageHasher::Identity => encoded_key.to_vec(),
+		StorageHasher::Blake2_128 => sp_core::blake2_128(encoded_key).to_vec(),
+		StorageHasher::Blake2_128Concat => {
+			// copied from substrate Blake2_128Concat::hash since StorageHasher is not public
+			sp_core::blake2_128(encoded_key).iter().chain(encoded_key).cloned().collect()
+		},
+		StorageHasher::Blake2_256 => sp_core::blake2_256(encoded_key).to_vec(),
+		StorageHasher::Twox128 => sp_core::twox_128(encoded_key).to_vec(),
+		StorageHasher::Twox256 => sp_core::twox_256(encoded_key).to_vec(),
+		StorageHasher::Twox64Concat =>
+			sp_core::twox_64(encoded_key).iter().chain(encoded_key).cloned().collect(),
+	}
+}
+
+impl KiltClient {
+	/// fetch a storage value and decode it, through a storage proof if a checkpoint is set
+	pub async fn storage<T: Decode>(
+		&self,
+		key: &StorageKey,
+		at: Option<Hash>,
+	) -> Result<Option<T>> {
+		match self.raw_storage(key, at).await? {
+			Some(data) => Ok(Some(Decode::decode(&mut data.0.as_slice())?)),
+			None => Ok(None),
+		}
+	}
+
+	pub async fn raw_storage(
+		&self,
+		key: &StorageKey,
+		at: Option<Hash>,
+	) -> Result<Option<StorageData>> {
+		if self.verifies_proofs() {
+			self.request_verified_storage(key, at).await
+		} else {
+			Ok(self.request_storage(key, at).await?)
+		}
+	}
+
+	/// fetch values of many keys at the same block, returned in the order of keys
+	pub async fn raw_storages(
+		&self,
+		keys: &[StorageKey],
+		at: Option<Hash>,
+	) -> Result<Vec<Result<Option<StorageData>>>> {
+		if self.verifies_proofs() {
+			return self.request_verified_storages(keys, at).await
+		}
+		let mut changes = self
+			.query_storage_at(keys, at)
+			.await?
+			.into_iter()
+			.flat_map(|set| set.changes.into_iter())
+			.collect::<HashMap<_, _>>();
+		let values = keys
+			.iter()
+			.map(|key| {
+				changes
+					.remove(key)
+					.ok_or_else(|| Error::StorageValueMissing(format!("0x{}", hex::encode(&key.0))))
+			})
+			.collect();
+		Ok(values)
+	}
+
+	/// one page of keys under `prefix`, strictly after `start_key`
+	pub async fn keys_paged(
+		&self,
+		prefix: &StorageKey,
+		count: u32,
+		start_key: Option<&StorageKey>,
+		at: Option<Hash>,
+	) -> Result<Vec<StorageKey>> {
+		let params = vec![
+			to_json_value(prefix)?,
+			to_json_value(count)?,
+			to_json_value(start_key)?,
+			to_json_value(at)?,
+		];
+		let keys = self.client.request("state_getKeysPaged", Some(params.into())).await?;
+		Ok(keys)
+	}
+
+	/// all keys under `prefix`, the finalized head is used if `at` is `None`.
+	/// note that the key list itself is not covered by storage proofs
+	pub async fn storage_keys(
+		&self,
+		prefix: &StorageKey,
+		at: Option<Hash>,
+	) -> Result<Vec<StorageKey>> {
+		// pin all pages to the same block
+		let at = match at {
+			Some(h) => h,
+			None => self.finalized_head().await?,
+		};
+		let mut keys: Vec<StorageKey> = vec![];
+		loop {
+			let page = self.keys_paged(prefix, KEYS_PAGE_SIZE, keys.last(), Some(at)).await?;
+			let is_last = page.len() < KEYS_PAGE_SIZE as usize;
+			keys.extend(page);
+			if is_last {
+				return Ok(keys)
+			}
+		}
+	}
+
+	/// all decoded values under `prefix`, e.g. a map or the rest of a partial n-map key
+	pub async fn iter_storage<T: Decode>(
+		&self,
+		prefix: &StorageKey,
+		at: Option<Hash>,
+	) -> Result<Vec<(StorageKey, T)>> {
+		let at = match at {
+			Some(h) => h,
+			None => self.finalized_head().await?,
+		};
+		let mut entries = vec![];
+		for keys in self.storage_keys(prefix, Some(at)).await?.chunks(KEYS_PAGE_SIZE as usize) {
+			let values = self.raw_storages(keys, Some(at)).await?;
+			for (key, value) in keys.iter().zip(values) {
+				// removed between listing and reading is impossible at a pinned block
+				if let Some(data) = value? {
+					entries.push((key.clone(), Decode::decode(&mut data.0.as_slice())?));
+				}
+			}
+		}
+		Ok(entries)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{key_hash, StorageEntry, StorageHasher};
+
+	const SYSTEM_NUMBER: StorageEntry = StorageEntry::new("System", "Number");
+	const SYSTEM_ACCOUNT: StorageEntry = StorageEntry::new("System", "Account");
+
+	#[test]
+	fn value_key_should_work() {
+		assert_eq!(
+			hex::encode(SYSTEM_NUMBER.value_key().0),
+			"26aa394eea5630e07c48ae0c9558cef702a5c1b19ab7a04f536c519aca4983ac"
+		);
+	}
+
+	#[test]
+	fn map_keys_should_extend_prefix() {
+		let hasher = StorageHasher::Blake2_128Concat;
+		let key = SYSTEM_ACCOUNT.map_key(&[1u8; 32], &hasher);
+		assert_eq!(
+			hex::encode(&key.0[..32]),
+			"26aa394eea5630e07c48ae0c9558cef7b99d880ec681799c0cf30e8886371da9"
+		);
+		// blake2_128 ++ raw key
+		assert_eq!(key.0.len(), 32 + 16 + 32);
+		assert_eq!(&key.0[48..], &[1u8; 32]);
+
+		let double =
+			SYSTEM_ACCOUNT.double_map_key(&1u32, &hasher, &2u64, &StorageHasher::Twox64Concat);
+		let partial = SYSTEM_ACCOUNT.map_key(&1u32, &hasher);
+		assert!(double.0.starts_with(&partial.0));
+		assert_eq!(&double.0[partial.0.len()..], key_hash(&2u64, &StorageHasher::Twox64Concat));
+
+		let n_map = SYSTEM_ACCOUNT.n_map_key(&[
+			(codec::Encode::encode(&1u32), hasher.clone()),
+			(codec::Encode::encode(&2u64), StorageHasher::Twox64Concat),
+		]);
+		assert_eq!(n_map, double);
+	}
+}
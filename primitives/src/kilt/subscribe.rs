@@ -0,0 +1,47 @@
+use std::{fmt, sync::Arc};
+
+use jsonrpsee::{
+	types::{to_json_value, traits::SubscriptionClient, Subscription},
+	ws_client::{WsClient, WsClientBuilder},
+};
+
+use super::{Error, Hash, Result, StorageChangeSet, StorageKey};
+
+/// websocket connection to kilt, used for subscriptions only
+#[derive(Clone)]
+pub struct KiltSubscriber {
+	client: Arc<WsClient>,
+	pub ip_address: String,
+}
+
+impl fmt::Debug for KiltSubscriber {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.debug_struct("KiltSubscriber").field("ip_address", &self.ip_address).finish()
+	}
+}
+
+impl KiltSubscriber {
+	pub async fn try_from_url(url: &str) -> Result<Self> {
+		if url.starts_with("ws://") || url.starts_with("wss://") {
+			let client = WsClientBuilder::default().build(url).await?;
+			Ok(KiltSubscriber { client: Arc::new(client), ip_address: url.to_string() })
+		} else {
+			Err(Error::UrlFormatError(
+				"Kilt subscription connection must start with ws or wss".to_owned(),
+			))
+		}
+	}
+
+	// the current values of keys are notified first, then every change of them
+	pub async fn subscribe_storage(
+		&self,
+		keys: &[StorageKey],
+	) -> Result<Subscription<StorageChangeSet<Hash>>> {
+		let params = vec![to_json_value(keys)?];
+		let subscription = self
+			.client
+			.subscribe("state_subscribeStorage", Some(params.into()), "state_unsubscribeStorage")
+			.await?;
+		Ok(subscription)
+	}
+}
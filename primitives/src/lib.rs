@@ -2,7 +2,7 @@ pub use codec::{Decode, Encode};
 pub use futures_timer::Delay;
 pub use serde::{Deserialize, Serialize};
 pub use sp_core::{
-	storage::{StorageData, StorageKey},
+	storage::{StorageChangeSet, StorageData, StorageKey},
 	Bytes, H256 as Hash,
 };
 use std::default::Default;
@@ -15,27 +15,41 @@ pub use web3::{
 	contract::{Contract, Options as Web3Options},
 	signing::{Key, SecretKeyRef},
 	transports::Http,
-	types::{Address, BlockNumber, FilterBuilder, Log, U64},
+	types::{Address, BlockNumber, FilterBuilder, Log, H256, H256 as TxHash, U256, U64},
 };
-pub use yaque::{Receiver as MqReceiver, Sender as MqSender};
 
-pub use config::{ChannelFiles, Config, ConfigInstance};
-pub use error::Error;
+pub use attestation::{AttestationSource, AttestationStatus};
+pub use config::{ChannelFiles, Config, ConfigInstance, SupervisorConfig};
+pub use db::{Database, RevocationRecord};
+pub use error::{Error, ErrorContext, ErrorKind, KeeperError};
 pub use ipfs::{IpfsClient, IpfsConfig};
-pub use kilt::{KiltClient, KiltConfig};
+pub use kilt::{KiltClient, KiltConfig, KiltSubscriber};
 pub use moonbeam::{MoonbeamClient, MoonbeamConfig};
-pub use traits::JsonParse;
-
-use crate::kilt::Attestation;
-
+pub use queue::{MqReceiver, MqSender, Queue, QueueConfig};
+pub use retry::{RetryPolicy, Retryable};
+pub use shutdown::{Shutdown, ShutdownTrigger};
+pub use stage::Stage;
+pub use traits::{Idempotent, JsonParse};
+pub use verdict::{Verdict, VerdictPolicy};
+
+pub mod attestation;
 pub mod config;
+pub mod db;
+pub mod dead_letter;
 pub mod error;
 pub mod ipfs;
 pub mod kilt;
+pub mod metrics;
 // #[cfg(feature = "monitor")]
 pub mod monitor;
 pub mod moonbeam;
+pub mod pipeline;
+pub mod queue;
+pub mod retry;
+pub mod shutdown;
+mod stage;
 mod traits;
+pub mod verdict;
 pub mod verify;
 
 // todo: move
@@ -43,7 +57,7 @@ pub const CHANNEL_LOG_TARGET: &str = "Channel";
 pub const MESSAGE_PARSE_LOG_TARGET: &str = "Message Parse";
 
 pub type Bytes32 = [u8; 32];
-pub type Result<T> = std::result::Result<T, (Option<U64>, error::Error)>;
+pub type Result<T> = std::result::Result<T, KeeperError>;
 
 #[derive(PartialEq, Eq, Debug, Default, Clone, Serialize, Deserialize)]
 pub struct ProofEvent {
@@ -57,6 +71,15 @@ pub struct ProofEvent {
 	pub(crate) request_hash: Bytes32,
 	pub(crate) root_hash: Bytes32,
 	pub(crate) expect_result: Vec<u128>,
+	// coordinates of the `AddProof` log
+	#[serde(default)]
+	pub(crate) block_hash: Option<H256>,
+	#[serde(default)]
+	pub(crate) transaction_hash: Option<TxHash>,
+	#[serde(default)]
+	pub(crate) transaction_index: Option<U64>,
+	#[serde(default)]
+	pub(crate) log_index: Option<U256>,
 }
 
 // # of elements in AddProof event
@@ -88,6 +111,10 @@ impl Detokenize for ProofEvent {
 			request_hash: proof_event_enum.6,
 			root_hash: proof_event_enum.7,
 			expect_result: proof_event_enum.8,
+			block_hash: None,
+			transaction_hash: None,
+			transaction_index: None,
+			log_index: None,
 		})
 	}
 }
@@ -96,6 +123,24 @@ impl ProofEvent {
 	pub fn set_block_number(&mut self, number: Option<U64>) {
 		self.block_number = number;
 	}
+
+	// record where the event is emitted
+	pub fn set_log(&mut self, log: &Log) {
+		self.block_number = log.block_number;
+		self.block_hash = log.block_hash;
+		self.transaction_hash = log.transaction_hash;
+		self.transaction_index = log.transaction_index;
+		self.log_index = log.log_index;
+	}
+
+	pub fn transaction_hash(&self) -> Option<TxHash> {
+		self.transaction_hash
+	}
+
+	pub fn log_index(&self) -> Option<U256> {
+		self.log_index
+	}
+
 	pub fn request_hash(&self) -> Bytes32 {
 		self.request_hash
 	}
@@ -146,6 +191,12 @@ impl ProofEvent {
 	}
 }
 
+impl Idempotent for ProofEvent {
+	fn idempotency_key(&self) -> Vec<u8> {
+		idempotency_key(self.transaction_hash, self.log_index, &self.request_hash)
+	}
+}
+
 pub type Events = Vec<ProofEvent>;
 
 // todo: no need? just use serde_json::parse
@@ -155,7 +206,43 @@ impl traits::JsonParse for Events {
 	}
 
 	fn try_from_bytes(json: &[u8]) -> std::result::Result<Self, error::Error> {
-		serde_json::from_slice(json).map_err(|e| e.into())
+		parse_message(json)
+	}
+}
+
+// a channel message carries one request, batches are from keepers before the split
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Message<T> {
+	One(T),
+	Batch(Vec<T>),
+}
+
+/// parse a channel message into the requests it carries
+pub fn parse_message<T: serde::de::DeserializeOwned>(
+	json: &[u8],
+) -> std::result::Result<Vec<T>, error::Error> {
+	match serde_json::from_slice(json)? {
+		Message::One(item) => Ok(vec![item]),
+		Message::Batch(items) => Ok(items),
+	}
+}
+
+// transaction hash ++ log index of the event, request hash for messages without them
+fn idempotency_key(
+	transaction_hash: Option<TxHash>,
+	log_index: Option<U256>,
+	request_hash: &Bytes32,
+) -> Vec<u8> {
+	match (transaction_hash, log_index) {
+		(Some(tx), Some(index)) => {
+			let mut key = tx.as_bytes().to_vec();
+			let mut index_bytes = [0u8; 32];
+			index.to_big_endian(&mut index_bytes);
+			key.extend_from_slice(&index_bytes);
+			key
+		},
+		_ => request_hash.to_vec(),
 	}
 }
 
@@ -168,13 +255,31 @@ pub struct VerifyResult {
 	pub program_hash: Bytes32,
 	pub request_hash: Bytes32,
 	pub attester: Bytes32,
-	pub is_passed: bool,
+	pub verdict: Verdict,
 	// exp_result in ProofEvent
 	pub calc_output: Vec<u128>,
+	// finalized kilt block the attestation is queried at
+	#[serde(default)]
+	pub kilt_block_hash: Option<Bytes32>,
+	// resolved identity of the attester
+	#[serde(default)]
+	pub attester_did: Option<String>,
+	#[serde(default)]
+	pub attester_web3_name: Option<String>,
+	#[serde(default)]
+	pub transaction_hash: Option<TxHash>,
+	#[serde(default)]
+	pub log_index: Option<U256>,
+}
+
+impl Idempotent for VerifyResult {
+	fn idempotency_key(&self) -> Vec<u8> {
+		idempotency_key(self.transaction_hash, self.log_index, &self.request_hash)
+	}
 }
 
 impl VerifyResult {
-	pub fn new_from_proof_event(p: ProofEvent, passed: bool) -> Self {
+	pub fn new_from_proof_event(p: ProofEvent, verdict: Verdict) -> Self {
 		VerifyResult {
 			number: p.block_number,
 			data_owner: p.data_owner,
@@ -183,19 +288,30 @@ impl VerifyResult {
 			program_hash: p.program_hash,
 			request_hash: p.request_hash,
 			attester: p.attester,
-			is_passed: passed,
+			verdict,
 			calc_output: p.expect_result,
+			kilt_block_hash: None,
+			attester_did: None,
+			attester_web3_name: None,
+			transaction_hash: p.transaction_hash,
+			log_index: p.log_index,
 		}
 	}
 
-	// if the credential is revoked by attester, do nothing and return error
-	pub fn update_from_attestation(&mut self, attest: Attestation) -> std::result::Result<(), ()> {
-		if !attest.revoked {
-			self.c_type = Bytes32::from(attest.ctype_hash);
-			self.attester = Bytes32::from(attest.attester);
-			Ok(())
-		} else {
-			Err(())
+	// the value submitted on-chain as `isPassed`
+	pub fn is_passed(&self) -> bool {
+		self.verdict.is_valid()
+	}
+
+	// downgrade the verdict if the credential is missing, revoked by attester, or
+	// attested for another ctype or by another attester than the event claims
+	pub fn check_attestation(&mut self, status: &AttestationStatus) {
+		self.verdict = match status.record() {
+			None => Verdict::AttestationMissing,
+			Some(record) if record.revoked => Verdict::AttestationRevoked,
+			Some(record) if record.ctype_hash != self.c_type => Verdict::CTypeMismatch,
+			Some(record) if record.attester != self.attester => Verdict::AttesterMismatch,
+			Some(_) => self.verdict,
 		}
 	}
 }
@@ -206,7 +322,13 @@ mod tests {
 
 	use web3::types::Address;
 
-	use crate::{traits::JsonParse, ProofEvent, VerifyResult};
+	use crate::{
+		attestation::{AttestationRecord, AttestationStatus},
+		kilt::Attestation,
+		parse_message,
+		traits::{Idempotent, JsonParse},
+		ProofEvent, TxHash, Verdict, VerifyResult,
+	};
 
 	#[test]
 	fn proof_event_parse_should_work() {
@@ -223,7 +345,7 @@ mod tests {
 
 	#[test]
 	fn verify_result_parse_should_work() {
-		let exp_verify_result_str = r#"{"number":"0x0","data_owner":"0x0000000000000000000000000000000000000000","root_hash":[0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0],"c_type":[0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0],"program_hash":[0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0],"request_hash":[0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0],"attester":[0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0],"is_passed":false,"calc_output":[]}"#;
+		let exp_verify_result_str = r#"{"number":null,"data_owner":"0x0000000000000000000000000000000000000000","root_hash":[0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0],"c_type":[0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0],"program_hash":[0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0],"request_hash":[0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0],"attester":[0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0],"verdict":"Unverified","calc_output":[],"kilt_block_hash":null,"attester_did":null,"attester_web3_name":null,"transaction_hash":null,"log_index":null}"#;
 		let _exp_verify_result_bytes = exp_verify_result_str.as_bytes();
 		let v_res = VerifyResult::default();
 		let v_res_bytes = serde_json::to_vec(&v_res).unwrap();
@@ -235,9 +357,62 @@ mod tests {
 		assert_eq!(v_res_str_decoded, v_res);
 	}
 
+	#[test]
+	fn check_attestation_should_downgrade_verdict() {
+		let claimed = VerifyResult {
+			c_type: [1u8; 32],
+			attester: [2u8; 32],
+			verdict: Verdict::Valid,
+			..Default::default()
+		};
+		let record =
+			AttestationRecord { ctype_hash: [1u8; 32], attester: [2u8; 32], revoked: false };
+		let attested_with = |record: AttestationRecord| AttestationStatus::Attested(record);
+
+		let mut missing = claimed.clone();
+		missing.check_attestation(&AttestationStatus::Missing);
+		assert_eq!(missing.verdict, Verdict::AttestationMissing);
+		assert!(!missing.is_passed());
+
+		let mut revoked = claimed.clone();
+		revoked.check_attestation(&attested_with(AttestationRecord {
+			revoked: true,
+			..record.clone()
+		}));
+		assert_eq!(revoked.verdict, Verdict::AttestationRevoked);
+
+		let mut wrong_ctype = claimed.clone();
+		wrong_ctype.check_attestation(&attested_with(AttestationRecord {
+			ctype_hash: [3u8; 32],
+			..record.clone()
+		}));
+		assert_eq!(wrong_ctype.verdict, Verdict::CTypeMismatch);
+		assert_eq!(wrong_ctype.c_type, [1u8; 32]);
+
+		let mut wrong_attester = claimed.clone();
+		wrong_attester.check_attestation(&attested_with(AttestationRecord {
+			attester: [3u8; 32],
+			..record.clone()
+		}));
+		assert_eq!(wrong_attester.verdict, Verdict::AttesterMismatch);
+		assert_eq!(wrong_attester.attester, [2u8; 32]);
+
+		// kilt attestations are normalized into the same record
+		let attest = Attestation {
+			ctype_hash: [1u8; 32].into(),
+			attester: [2u8; 32].into(),
+			..Default::default()
+		};
+		assert_eq!(AttestationStatus::from(Some(&attest)), attested_with(record));
+
+		let mut attested = claimed.clone();
+		attested.check_attestation(&AttestationStatus::from(Some(&attest)));
+		assert!(attested.is_passed());
+	}
+
 	#[test]
 	fn event_result_parse_should_work() {
-		let json_str = r#"[{"block_number": "0x21", "data_owner":"0x127221418abcd357022d29f62449d98d9610dfab","attester":[76,253,46,114,43,55,11,16,21,52,58,39,201,120,152,21,216,3,253,177,132,10,170,4,6,162,107,229,90,149,255,1],"c_type":[127,46,247,33,178,146,185,183,214,120,233,248,42,176,16,225,57,96,5,88,223,128,91,188,97,160,4,30,96,182,26,24],"program_hash":[138,207,143,54,219,208,64,124,237,34,124,151,249,241,188,249,137,198,175,253,50,35,26,213,106,54,233,223,205,73,38,16],"field_name":"age","proof_cid":"QmUn4UfXdv7uJXerqy1PMfnXxYuM3xfpUC8pFZaVyJoN7H","request_hash":[94,173,49,247,138,238,243,148,66,124,21,189,107,13,78,210,69,212,74,170,249,110,90,37,128,46,16,119,10,76,17,117],"root_hash":[175,110,140,119,75,15,116,9,116,63,126,40,226,159,211,25,109,14,238,114,198,110,87,197,80,48,42,190,164,51,105,51],"expect_result":[1]}]"#;
+		let json_str = r#"[{"block_number":"0x21","data_owner":"0x127221418abcd357022d29f62449d98d9610dfab","attester":[76,253,46,114,43,55,11,16,21,52,58,39,201,120,152,21,216,3,253,177,132,10,170,4,6,162,107,229,90,149,255,1],"c_type":[127,46,247,33,178,146,185,183,214,120,233,248,42,176,16,225,57,96,5,88,223,128,91,188,97,160,4,30,96,182,26,24],"program_hash":[138,207,143,54,219,208,64,124,237,34,124,151,249,241,188,249,137,198,175,253,50,35,26,213,106,54,233,223,205,73,38,16],"field_names":[6383461],"proof_cid":"QmUn4UfXdv7uJXerqy1PMfnXxYuM3xfpUC8pFZaVyJoN7H","request_hash":[94,173,49,247,138,238,243,148,66,124,21,189,107,13,78,210,69,212,74,170,249,110,90,37,128,46,16,119,10,76,17,117],"root_hash":[175,110,140,119,75,15,116,9,116,63,126,40,226,159,211,25,109,14,238,114,198,110,87,197,80,48,42,190,164,51,105,51],"expect_result":[1],"block_hash":null,"transaction_hash":null,"transaction_index":null,"log_index":null}]"#;
 		let mut test_event = vec![];
 
 		test_event.push(ProofEvent {
@@ -267,12 +442,49 @@ mod tests {
 				238, 114, 198, 110, 87, 197, 80, 48, 42, 190, 164, 51, 105, 51,
 			],
 			expect_result: vec![1],
+			block_hash: None,
+			transaction_hash: None,
+			transaction_index: None,
+			log_index: None,
 		});
 
 		let event_str = test_event.into_bytes().unwrap();
 		assert_eq!(std::str::from_utf8(&event_str).unwrap(), json_str);
 	}
 
+	#[test]
+	fn message_should_carry_one_request_or_a_batch() {
+		let one = VerifyResult { request_hash: [1u8; 32], ..Default::default() };
+		let other = VerifyResult { request_hash: [2u8; 32], ..Default::default() };
+		let batch = vec![one.clone(), other];
+
+		let parsed: Vec<VerifyResult> = parse_message(&serde_json::to_vec(&one).unwrap()).unwrap();
+		assert_eq!(parsed, vec![one]);
+		let parsed: Vec<VerifyResult> =
+			parse_message(&serde_json::to_vec(&batch).unwrap()).unwrap();
+		assert_eq!(parsed, batch);
+		assert!(parse_message::<VerifyResult>(b"{").is_err());
+	}
+
+	#[test]
+	fn idempotency_key_should_prefer_log_coordinates() {
+		let event = ProofEvent { request_hash: [1u8; 32], ..Default::default() };
+		assert_eq!(event.idempotency_key(), vec![1u8; 32]);
+
+		let logged = ProofEvent {
+			transaction_hash: Some(TxHash::repeat_byte(2)),
+			log_index: Some(3.into()),
+			..event.clone()
+		};
+		let result = VerifyResult::new_from_proof_event(logged.clone(), Verdict::Valid);
+		assert_eq!(logged.idempotency_key().len(), 64);
+		assert_eq!(logged.idempotency_key(), result.idempotency_key());
+		assert_ne!(
+			logged.idempotency_key(),
+			ProofEvent { log_index: Some(4.into()), ..logged.clone() }.idempotency_key()
+		);
+	}
+
 	#[test]
 	fn bytes32_segament_parse_should_correct() {
 		// 6b696c744163636f756e74000000000000000000000000000000000000000000
@@ -0,0 +1,200 @@
+use std::{fmt, future::Future, net::SocketAddr, time::Duration};
+
+use prometheus::{
+	core::Collector, Encoder, Gauge, Histogram, HistogramOpts, HistogramVec, IntCounter,
+	IntCounterVec, IntGauge, IntGaugeVec, Opts, Registry, TextEncoder,
+};
+
+use super::{Deserialize, Serialize, U256, U64};
+
+pub const METRICS_LOG_TARGET: &str = "Metrics";
+// `service` label of requests
+pub const IPFS_SERVICE: &str = "ipfs";
+pub const KILT_SERVICE: &str = "kilt";
+
+const NAMESPACE: &str = "keeper";
+// wei in a GLMR
+const WEI_PER_TOKEN: f64 = 1e18;
+
+/// where the prometheus endpoint listens, none by default
+#[derive(Eq, PartialEq, Clone, Debug, Default, Deserialize, Serialize)]
+pub struct MetricsConfig {
+	// e.g. 127.0.0.1:9615, serves `/metrics`
+	#[serde(default)]
+	pub listen: Option<SocketAddr>,
+}
+
+/// prometheus metrics of a keeper process, clones share the values
+#[derive(Clone)]
+pub struct Metrics {
+	registry: Registry,
+	scan_block: IntGauge,
+	scan_lag: IntGauge,
+	stage_events: IntCounterVec,
+	verifications: IntCounterVec,
+	verify_duration: Histogram,
+	request_duration: HistogramVec,
+	request_errors: IntCounterVec,
+	submitted_txs: IntCounter,
+	gas_used: IntCounter,
+	keeper_balance: Gauge,
+	// in the unit of `Queue::depth`
+	queue_depth: IntGaugeVec,
+}
+
+// names and labels are fixed, so registering them never fails
+fn register<M: Collector + Clone + 'static>(registry: &Registry, metric: M) -> M {
+	registry
+		.register(Box::new(metric.clone()))
+		.expect("metrics are registered once");
+	metric
+}
+
+impl Metrics {
+	pub fn new() -> Self {
+		let registry = Registry::new();
+		let opts = |name: &str, help: &str| Opts::new(name, help).namespace(NAMESPACE);
+		let histogram =
+			|name: &str, help: &str| HistogramOpts::new(name, help).namespace(NAMESPACE);
+		let invalid = "metric options are valid";
+
+		let scan_block = IntGauge::with_opts(opts("scan_block", "next moonbeam block to scan"));
+		let scan_lag = IntGauge::with_opts(opts("scan_lag_blocks", "blocks the scan is behind"));
+		let stage_events = IntCounterVec::new(
+			opts("stage_events_total", "requests handled by each stage"),
+			&["stage"],
+		);
+		let verifications = IntCounterVec::new(
+			opts("verifications_total", "proof verifications, by result"),
+			&["result"],
+		);
+		let verify_duration =
+			Histogram::with_opts(histogram("verify_duration_seconds", "proof verification time"));
+		let request_duration = HistogramVec::new(
+			histogram("request_duration_seconds", "requests to ipfs and kilt, retries included"),
+			&["service"],
+		);
+		let request_errors = IntCounterVec::new(
+			opts("request_errors_total", "requests to ipfs and kilt failing after retries"),
+			&["service"],
+		);
+		let submitted_txs =
+			IntCounter::with_opts(opts("submitted_transactions_total", "verdicts submitted"));
+		let gas_used = IntCounter::with_opts(opts("gas_used_total", "gas used by submissions"));
+		let keeper_balance =
+			Gauge::with_opts(opts("keeper_balance", "balance of the keeper account in GLMR"));
+		let queue_depth =
+			IntGaugeVec::new(opts("queue_depth", "messages waiting in a channel"), &["channel"]);
+
+		Metrics {
+			scan_block: register(&registry, scan_block.expect(invalid)),
+			scan_lag: register(&registry, scan_lag.expect(invalid)),
+			stage_events: register(&registry, stage_events.expect(invalid)),
+			verifications: register(&registry, verifications.expect(invalid)),
+			verify_duration: register(&registry, verify_duration.expect(invalid)),
+			request_duration: register(&registry, request_duration.expect(invalid)),
+			request_errors: register(&registry, request_errors.expect(invalid)),
+			submitted_txs: register(&registry, submitted_txs.expect(invalid)),
+			gas_used: register(&registry, gas_used.expect(invalid)),
+			keeper_balance: register(&registry, keeper_balance.expect(invalid)),
+			queue_depth: register(&registry, queue_depth.expect(invalid)),
+			registry,
+		}
+	}
+
+	pub fn set_scan_progress(&self, next: U64, best: U64) {
+		self.scan_block.set(next.as_u64() as i64);
+		self.scan_lag.set(best.saturating_sub(next).as_u64() as i64);
+	}
+
+	pub fn inc_stage_events(&self, stage: &str, count: usize) {
+		self.stage_events.with_label_values(&[stage]).inc_by(count as u64);
+	}
+
+	/// `None` if the verifier failed to run
+	pub fn observe_verification(&self, passed: Option<bool>, elapsed: Duration) {
+		let result = match passed {
+			Some(true) => "passed",
+			Some(false) => "failed",
+			None => "error",
+		};
+		self.verifications.with_label_values(&[result]).inc();
+		self.verify_duration.observe(elapsed.as_secs_f64());
+	}
+
+	/// time a request to `service`, and count it if it fails
+	pub async fn observe_request<T, E, F>(&self, service: &str, request: F) -> Result<T, E>
+	where
+		F: Future<Output = Result<T, E>>,
+	{
+		let timer = self.request_duration.with_label_values(&[service]).start_timer();
+		let res = request.await;
+		timer.observe_duration();
+		if res.is_err() {
+			self.request_errors.with_label_values(&[service]).inc();
+		}
+		res
+	}
+
+	pub fn observe_submission(&self, gas_used: Option<U256>) {
+		self.submitted_txs.inc();
+		self.gas_used.inc_by(gas_used.unwrap_or_default().low_u64());
+	}
+
+	pub fn set_keeper_balance(&self, wei: U256) {
+		self.keeper_balance.set(wei.low_u128() as f64 / WEI_PER_TOKEN);
+	}
+
+	pub fn set_queue_depth(&self, channel: &str, depth: u64) {
+		self.queue_depth.with_label_values(&[channel]).set(depth as i64);
+	}
+
+	pub fn queue_depth(&self, channel: &str) -> u64 {
+		self.queue_depth.with_label_values(&[channel]).get() as u64
+	}
+
+	/// all metrics in the prometheus text format
+	pub fn encode(&self) -> String {
+		let mut buffer = vec![];
+		if let Err(e) = TextEncoder::new().encode(&self.registry.gather(), &mut buffer) {
+			log::error!(target: METRICS_LOG_TARGET, "fail to encode metrics, err: {:?}", e);
+		}
+		String::from_utf8(buffer).unwrap_or_default()
+	}
+}
+
+impl Default for Metrics {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl fmt::Debug for Metrics {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.debug_struct("Metrics").finish_non_exhaustive()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::time::Duration;
+
+	use super::{Metrics, KILT_SERVICE};
+
+	#[tokio::test]
+	async fn metrics_should_be_encoded() {
+		let metrics = Metrics::new();
+		metrics.set_scan_progress(90.into(), 100.into());
+		metrics.observe_verification(Some(true), Duration::from_millis(20));
+		metrics.set_queue_depth("event2ipfs", 3);
+		let res: Result<(), ()> = metrics.observe_request(KILT_SERVICE, async { Err(()) }).await;
+		assert!(res.is_err());
+
+		let text = metrics.encode();
+		assert!(text.contains("keeper_scan_lag_blocks 10"));
+		assert!(text.contains("keeper_verifications_total{result=\"passed\"} 1"));
+		assert!(text.contains("keeper_queue_depth{channel=\"event2ipfs\"} 3"));
+		assert!(text.contains("keeper_request_errors_total{service=\"kilt\"} 1"));
+		assert_eq!(metrics.queue_depth("event2ipfs"), 3);
+	}
+}
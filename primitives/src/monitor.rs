@@ -7,7 +7,7 @@ use tokio::{
 	time::Duration,
 };
 
-use super::{Address, Deserialize, Serialize, U64};
+use super::{Address, Deserialize, KeeperError, Serialize, U64};
 
 const TIME_OUT: Duration = Duration::from_secs(5);
 
@@ -36,15 +36,15 @@ pub type KeywordReplace = HashMap<String, String>;
 impl MonitorMetrics {
 	pub fn new(
 		target: String,
-		block_number: Option<U64>,
-		error: &super::Error,
+		error: &KeeperError,
 		keeper_address: Address,
-		client_address: &String,
+		client_address: &str,
 	) -> Self {
-		let error_msg = format!("{:?}", error);
+		// stage, request hash and cid are part of the message
+		let error_msg = error.to_string();
 		Self {
 			target,
-			block_number,
+			block_number: error.block,
 			error_msg,
 			keeper_address,
 			client_address: String::from(client_address),
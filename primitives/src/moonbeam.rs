@@ -12,7 +12,6 @@ use web3::{
 
 pub use super::*;
 use super::{Deserialize, Serialize};
-pub const SUBMIT_TX_MAX_RETRY_TIMES: usize = 3;
 pub const MOONBEAM_SCAN_SPAN: usize = 10;
 // TODO: move it to config file
 pub const MOONBEAM_LISTENED_EVENT: &'static str = "AddProof";
@@ -26,6 +25,9 @@ pub const MOONBEAM_QUERY_LOG_TARGET: &str = "MoonbeamQuery";
 pub const SUBMIT_VERIFICATION: &str = "submit";
 pub const SUBMIT_STATUS_QUERY: &str = "hasSubmitted";
 pub const IS_FINISHED: &str = "isFinished";
+pub const CHECK_ATTESTATION: &str = "checkAttestation";
+// attestation registry view
+pub const GET_ATTESTATION: &str = "getAttestation";
 
 #[derive(Eq, PartialEq, Clone, Debug, Deserialize, Serialize)]
 pub struct MoonbeamConfig {
@@ -35,31 +37,42 @@ pub struct MoonbeamConfig {
 	// where keeper submit the verify result
 	pub write_contract: String,
 	pub private_key: String,
+	#[serde(default)]
+	pub retry: RetryPolicy,
 }
 
 #[derive(Clone, Debug)]
 pub struct MoonbeamClient {
 	inner: Web3<Http>,
 	pub ip_address: String,
+	pub retry: RetryPolicy,
 }
 
 impl MoonbeamClient {
 	pub fn new(url: String) -> Result<Self> {
 		if url.starts_with("http") {
 			let web3 = Web3::new(Http::new(&url)?);
-			Ok(MoonbeamClient { inner: web3, ip_address: url })
+			Ok(MoonbeamClient { inner: web3, ip_address: url, retry: Default::default() })
 		} else {
 			Err(Error::ClientCreationError("Wrong Moonbeam connection url".to_owned()))
 		}
 	}
 
+	pub fn with_retry(mut self, retry: RetryPolicy) -> Self {
+		self.retry = retry;
+		self
+	}
+
 	pub fn eth(&self) -> Eth<Http> {
 		self.inner.eth()
 	}
 
 	pub async fn best_number(&self) -> Result<U64> {
-		let maybe_best = self.eth().block_number().await;
-		maybe_best.map_err(|e| e.into())
+		let eth = &self.eth();
+		let query = move || async move { eth.block_number().await.map_err(Error::from) };
+		self.retry
+			.retry(MOONBEAM_QUERY_LOG_TARGET, "query best block number", query)
+			.await
 	}
 
 	// get proof contract
@@ -84,6 +97,17 @@ impl MoonbeamClient {
 		Ok(contract)
 	}
 
+	// get attestation registry contract, an alternative attestation source to kilt
+	pub fn attestation_registry_contract(&self, contract_addr: &str) -> Result<Contract<Http>> {
+		let address = utils::trim_address_str(contract_addr)?;
+		let contract = Contract::from_json(
+			self.inner.eth(),
+			address,
+			include_bytes!("../contracts/AttestationRegistry.json"),
+		)?;
+		Ok(contract)
+	}
+
 	#[cfg(test)]
 	pub fn events_contract(&self, contract_addr: &str) -> Result<Contract<Http>> {
 		let address = utils::trim_address_str(contract_addr)?;
@@ -110,73 +134,27 @@ pub mod utils {
 		func_2: &str,
 		params_2: P2,
 		request_hash: Bytes32,
-		query_times: usize,
+		retry: &RetryPolicy,
 	) -> Result<(bool, bool)> {
-		let mut maybe_fun_1_query_result =
-			contract.query(func_1, params_1, None, Web3Options::default(), None).await;
-		let mut maybe_fun_2_query_result =
-			contract.query(func_2, params_2, None, Web3Options::default(), None).await;
-		match (maybe_fun_1_query_result, maybe_fun_2_query_result) {
-			(Ok(query_fuc_1_result), Ok(query_fun_2_result)) =>
-				return Ok((query_fuc_1_result, query_fun_2_result)),
-			_ => {
-				let mut maybe_fun_1_retry_result =
-					contract.query(func_1, params_1, None, Web3Options::default(), None).await;
-				let mut maybe_fun_2_retry_result =
-					contract.query(func_2, params_2, None, Web3Options::default(), None).await;
-				for i in 0..query_times {
-					if maybe_fun_1_retry_result.is_ok() && maybe_fun_2_retry_result.is_ok() {
-						return Ok((
-							maybe_fun_1_retry_result.unwrap(),
-							maybe_fun_2_retry_result.unwrap(),
-						))
-					} else {
-						maybe_fun_1_retry_result = contract
-							.query(func_1, params_1, None, Web3Options::default(), None)
-							.await;
-						maybe_fun_2_retry_result = contract
-							.query(func_2, params_2, None, Web3Options::default(), None)
-							.await;
-					}
-				}
-				match (maybe_fun_1_retry_result, maybe_fun_2_retry_result) {
-					(Ok(maybe_fun_1_retry_result), Err(maybe_fun_2_retry_result)) => {
-						log::warn!(
-							target: MOONBEAM_QUERY_LOG_TARGET,
-							"The {:?} query for request hash[{:?}] meets error: [{:?}]",
-							func_2,
-							hex::encode(request_hash),
-							maybe_fun_2_retry_result
-						);
-						return Err(maybe_fun_2_retry_result.into())
-					},
-					(Err(maybe_fun_1_retry_result), Ok(maybe_fun_2_retry_result)) => {
-						log::warn!(
-							target: MOONBEAM_QUERY_LOG_TARGET,
-							"The {:?} query for request hash[{:?}] meets error: [{:?}]",
-							func_1,
-							hex::encode(request_hash),
-							maybe_fun_1_retry_result
-						);
-						return Err(maybe_fun_1_retry_result.into())
-					},
-					(Err(maybe_fun_1_retry_result), Err(maybe_fun_2_retry_result)) => {
-						log::warn!(
-						target: MOONBEAM_QUERY_LOG_TARGET,
-						"The {:?} and {:?} query for request hash[{:?}] meets error: [{:?} and {:?}]",
-						func_1,
-						func_2,
-						hex::encode(request_hash),
-						maybe_fun_1_retry_result,
-						maybe_fun_2_retry_result
-					);
-						return Err(maybe_fun_1_retry_result.into())
-					},
-					(Ok(maybe_fun_1_retry_result), Ok(maybe_fun_2_retry_result)) =>
-						return Ok((maybe_fun_1_retry_result, maybe_fun_2_retry_result)),
-				}
-			},
-		}
+		let query = move || async move {
+			let result_1 =
+				contract.query(func_1, params_1, None, Web3Options::default(), None).await?;
+			let result_2 =
+				contract.query(func_2, params_2, None, Web3Options::default(), None).await?;
+			Ok::<_, Error>((result_1, result_2))
+		};
+		let what = format!("{} and {} query", func_1, func_2);
+		retry.retry(MOONBEAM_QUERY_LOG_TARGET, &what, query).await.map_err(|e| {
+			log::warn!(
+				target: MOONBEAM_QUERY_LOG_TARGET,
+				"The {:?} and {:?} query for request hash[{:?}] meets error: [{:?}]",
+				func_1,
+				func_2,
+				hex::encode(request_hash),
+				e
+			);
+			e
+		})
 	}
 
 	// todo: test if if can filter event due to contract address
@@ -270,6 +248,23 @@ pub enum Error {
 	InvalidEthereumAddress(String),
 }
 
+// the node is unreachable or the request is lost, a failed call is not retried
+impl Retryable for web3::Error {
+	fn is_retryable(&self) -> bool {
+		matches!(self, web3::Error::Unreachable | web3::Error::Transport(_) | web3::Error::Io(_))
+	}
+}
+
+impl Retryable for Error {
+	fn is_retryable(&self) -> bool {
+		match self {
+			Error::Web3Error(e) | Error::Web3ContractError(Web3ContractErr::Api(e)) =>
+				e.is_retryable(),
+			_ => false,
+		}
+	}
+}
+
 pub type Result<T> = std::result::Result<T, Error>;
 
 #[cfg(test)]
@@ -0,0 +1,127 @@
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use serde::de::DeserializeOwned;
+
+use super::{
+	dead_letter::DEAD_LETTER_LOG_TARGET, parse_message, queue::Delivery, ConfigInstance,
+	ErrorContext, ErrorKind, Idempotent, MqReceiver, MqSender, Result, Serialize, Shutdown,
+	CHANNEL_LOG_TARGET, MESSAGE_PARSE_LOG_TARGET,
+};
+
+// how long a stage waits for a message before polling again
+const RECV_TIMEOUT: Duration = Duration::from_secs(1);
+// processed requests are only needed while their message may be redelivered
+const PROCESSED_RETENTION: Duration = Duration::from_secs(7 * 24 * 3600);
+const PRUNE_INTERVAL: Duration = Duration::from_secs(3600);
+
+/// a step of the pipeline, consuming typed requests from one channel and sending
+/// its outputs to the next one
+#[async_trait]
+pub trait Stage: Send + Sync {
+	type Input: DeserializeOwned + Idempotent + Clone + Send + Sync;
+	type Output: Serialize + Send + Sync;
+
+	/// dead letters and processed requests are kept under this name
+	const KIND: super::Stage;
+	/// log target, also names the stage in monitor alerts
+	const LOG_TARGET: &'static str;
+
+	/// address of the service the stage relies on, shown in monitor alerts
+	fn endpoint<'a>(&self, config: &'a ConfigInstance) -> &'a str;
+
+	/// requests of one message, those already processed are filtered out
+	async fn process(
+		&self,
+		config: &ConfigInstance,
+		inputs: Vec<Self::Input>,
+	) -> Result<Vec<Self::Output>>;
+}
+
+/// feed messages of `receiver` to the stage until the channel fails or a shutdown is
+/// requested, outputs go to `sender` and the message is committed once all of them are
+/// sent. requests failing with an item error are moved to dead letters, other errors stop
+/// the run
+pub async fn run_stage<S: Stage>(
+	stage: &S,
+	config: &ConfigInstance,
+	receiver: &mut MqReceiver,
+	mut sender: Option<&mut MqSender>,
+	shutdown: &Shutdown,
+) -> Result<()> {
+	let mut next_prune = Instant::now();
+	// a message received is finished before stopping, the next ones stay in the channel
+	while !shutdown.is_requested() {
+		if Instant::now() >= next_prune {
+			let pruned = config
+				.database
+				.prune_processed(S::KIND, PROCESSED_RETENTION)
+				.in_stage(S::KIND)?;
+			log::debug!(target: S::LOG_TARGET, "pruned {} processed requests", pruned);
+			next_prune = Instant::now() + PRUNE_INTERVAL;
+		}
+		let message = match receiver.recv_timeout(RECV_TIMEOUT).await {
+			Ok(Some(m)) => m,
+			Ok(None) => continue,
+			Err(e) => return Err(e).in_stage(S::KIND),
+		};
+		log::info!(target: CHANNEL_LOG_TARGET, "recv msg in {} stage", S::KIND);
+
+		let inputs = match parse_message::<S::Input>(&message) {
+			Ok(inputs) => inputs,
+			Err(e) => {
+				log::error!(
+					target: MESSAGE_PARSE_LOG_TARGET,
+					"messages in {} stage wrongly parsed, {:?}",
+					S::KIND,
+					e
+				);
+				// move the message aside, otherwise it blocks the channel forever
+				dead_letter::<S>(config, message, e.to_string()).await?;
+				continue
+			},
+		};
+
+		// skip requests handled before the message was redelivered
+		let inputs = config.database.unprocessed(S::KIND, inputs).in_stage(S::KIND)?;
+		let processed = inputs.clone();
+		let outputs = match stage.process(config, inputs).await.in_stage(S::KIND) {
+			Ok(outputs) => outputs,
+			Err(e) if e.kind() == ErrorKind::Item => {
+				log::error!(target: S::LOG_TARGET, "request can not be handled: {}", e);
+				dead_letter::<S>(config, message, e.to_string()).await?;
+				continue
+			},
+			Err(e) => return Err(e),
+		};
+
+		if let Some(sender) = &mut sender {
+			// one request per message, so each is handled and committed on its own downstream
+			for output in &outputs {
+				let bytes = serde_json::to_vec(output).in_stage(S::KIND)?;
+				sender.send(bytes).await.in_stage(S::KIND)?;
+			}
+		}
+		for input in &processed {
+			config.database.mark_processed(S::KIND, input).in_stage(S::KIND)?;
+		}
+		message.commit().await.in_stage(S::KIND)?;
+		config.metrics.inc_stage_events(&S::KIND.to_string(), processed.len());
+	}
+
+	Ok(())
+}
+
+// keep the message in the stage's dead letters and go on with the next one
+async fn dead_letter<S: Stage>(
+	config: &ConfigInstance,
+	message: Delivery<'_>,
+	reason: String,
+) -> Result<()> {
+	let id = config
+		.database
+		.insert_dead_letter(S::KIND, reason, &message)
+		.in_stage(S::KIND)?;
+	log::warn!(target: DEAD_LETTER_LOG_TARGET, "moved to {} dead letter #{}", S::KIND, id);
+	message.commit().await.in_stage(S::KIND)
+}
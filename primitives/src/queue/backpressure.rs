@@ -0,0 +1,152 @@
+use std::{sync::Arc, time::Duration};
+
+use super::{Deserialize, Queue, Result, Serialize, QUEUE_LOG_TARGET};
+use crate::{metrics::Metrics, Shutdown};
+
+fn default_check_interval_secs() -> u64 {
+	5
+}
+
+/// depths of a channel to pause its producers at, and to resume them at
+#[derive(Eq, PartialEq, Clone, Copy, Debug, Deserialize, Serialize)]
+pub struct WaterMarks {
+	pub high: u64,
+	pub low: u64,
+}
+
+/// limits of the channels, in the unit of `Queue::depth`. a channel without marks grows
+/// without limit
+#[derive(Eq, PartialEq, Clone, Debug, Deserialize, Serialize)]
+pub struct BackpressureConfig {
+	#[serde(default)]
+	pub event_to_ipfs: Option<WaterMarks>,
+	#[serde(default)]
+	pub verify_to_attest: Option<WaterMarks>,
+	#[serde(default)]
+	pub attest_to_submit: Option<WaterMarks>,
+	// how often depths are checked while paused
+	#[serde(default = "default_check_interval_secs")]
+	pub check_interval_secs: u64,
+}
+
+impl Default for BackpressureConfig {
+	fn default() -> Self {
+		BackpressureConfig {
+			event_to_ipfs: None,
+			verify_to_attest: None,
+			attest_to_submit: None,
+			check_interval_secs: default_check_interval_secs(),
+		}
+	}
+}
+
+#[derive(Clone)]
+struct Channel {
+	name: String,
+	queue: Arc<dyn Queue>,
+	marks: Option<WaterMarks>,
+}
+
+/// holds a producer back while a channel after it is over its high-water mark, until all
+/// of them are below their low-water marks
+#[derive(Clone)]
+pub struct Backpressure {
+	channels: Vec<Channel>,
+	check_interval: Duration,
+	metrics: Metrics,
+	paused: bool,
+}
+
+impl Backpressure {
+	pub fn new(check_interval_secs: u64, metrics: Metrics) -> Self {
+		let check_interval = Duration::from_secs(check_interval_secs);
+		Backpressure { channels: vec![], check_interval, metrics, paused: false }
+	}
+
+	/// a channel after the producer, its depth is reported even without marks
+	pub fn watch(mut self, name: &str, queue: Arc<dyn Queue>, marks: Option<WaterMarks>) -> Self {
+		self.channels.push(Channel { name: name.to_owned(), queue, marks });
+		self
+	}
+
+	/// returns right away if the channels have room, otherwise once they drained or a
+	/// shutdown is requested
+	pub async fn wait(&mut self, shutdown: &mut Shutdown) -> Result<()> {
+		loop {
+			if !self.check().await? || shutdown.is_requested() {
+				return Ok(())
+			}
+			tokio::select! {
+				_ = tokio::time::sleep(self.check_interval) => {},
+				_ = shutdown.requested() => {},
+			}
+		}
+	}
+
+	// record the depths, and whether the producer is paused after them
+	async fn check(&mut self) -> Result<bool> {
+		let (mut over_high, mut over_low) = (None, false);
+		for channel in &self.channels {
+			let depth = channel.queue.depth().await?;
+			self.metrics.set_queue_depth(&channel.name, depth);
+			if let Some(marks) = channel.marks {
+				if depth > marks.high {
+					over_high = over_high.or(Some((&channel.name, depth)));
+				}
+				over_low |= depth > marks.low;
+			}
+		}
+
+		match (self.paused, over_high) {
+			(false, Some((name, depth))) => {
+				log::warn!(target: QUEUE_LOG_TARGET, "{} holds {}, pause producing", name, depth);
+				self.paused = true;
+			},
+			(true, _) if !over_low => {
+				log::info!(target: QUEUE_LOG_TARGET, "channels drained, resume producing");
+				self.paused = false;
+			},
+			_ => {},
+		}
+		Ok(self.paused)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::sync::Arc;
+
+	use super::{Backpressure, WaterMarks};
+	use crate::{
+		metrics::Metrics,
+		queue::{MemoryQueue, Queue},
+	};
+
+	#[tokio::test]
+	async fn producer_should_pause_between_water_marks() {
+		let queue = MemoryQueue::default();
+		let mut sender = queue.sender().await.unwrap();
+		let metrics = Metrics::default();
+		let marks = Some(WaterMarks { high: 2, low: 0 });
+		let mut backpressure =
+			Backpressure::new(1, metrics.clone()).watch("events", Arc::new(queue.clone()), marks);
+
+		for _ in 0..3 {
+			sender.send(b"event".to_vec()).await.unwrap();
+		}
+		assert!(backpressure.check().await.unwrap());
+		assert_eq!(metrics.queue_depth("events"), 3);
+
+		// still above the low-water mark
+		let mut receiver = queue.receiver().await.unwrap();
+		for _ in 0..2 {
+			let delivery = receiver.recv_timeout(Default::default()).await.unwrap().unwrap();
+			delivery.commit().await.unwrap();
+		}
+		assert!(backpressure.check().await.unwrap());
+
+		let delivery = receiver.recv_timeout(Default::default()).await.unwrap().unwrap();
+		delivery.commit().await.unwrap();
+		assert!(!backpressure.check().await.unwrap());
+	}
+}
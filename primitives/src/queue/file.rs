@@ -0,0 +1,186 @@
+use std::{
+	fs, io,
+	path::{Path, PathBuf},
+	time::Duration,
+};
+
+use async_trait::async_trait;
+use yaque::{recovery, Receiver, RecvGuard, Sender};
+
+use super::{
+	Acknowledge, Delivery, MqReceiver, MqSender, Queue, QueueReceiver, QueueSender, Result,
+};
+use crate::Delay;
+
+// messages sent and committed so far, each written by its own side only
+const SENT_COUNTER: &str = "sent";
+const COMMITTED_COUNTER: &str = "committed";
+
+/// yaque queue in a directory, shared by processes on the same host
+#[derive(Clone, Debug)]
+pub struct FileQueue {
+	path: PathBuf,
+}
+
+impl FileQueue {
+	pub fn new(path: &Path) -> Self {
+		FileQueue { path: path.to_path_buf() }
+	}
+}
+
+#[async_trait]
+impl Queue for FileQueue {
+	async fn sender(&self) -> Result<MqSender> {
+		let inner = Sender::open(&self.path)?;
+		let sent = Counter::open(&self.path, SENT_COUNTER)?;
+		Ok(Box::new(FileSender { inner, sent }))
+	}
+
+	async fn receiver(&self) -> Result<MqReceiver> {
+		let inner = Receiver::open(&self.path)?;
+		let committed = Counter::open(&self.path, COMMITTED_COUNTER)?;
+		let sent_path = self.path.join(SENT_COUNTER);
+		Ok(Box::new(FileReceiver { inner, committed, sent_path }))
+	}
+
+	// yaque does not count messages, the sides keep their counts next to the segments
+	async fn depth(&self) -> Result<u64> {
+		let sent = read_counter(&self.path.join(SENT_COUNTER))?;
+		let committed = read_counter(&self.path.join(COMMITTED_COUNTER))?;
+		Ok(sent.saturating_sub(committed))
+	}
+
+	fn unlock_sender(&self) -> Result<()> {
+		recovery::unlock_for_sending(&self.path)?;
+		Ok(())
+	}
+
+	fn unlock_receiver(&self) -> Result<()> {
+		recovery::unlock_for_receiving(&self.path)?;
+		Ok(())
+	}
+}
+
+/// count persisted in a file of the queue directory, replaced on every update
+#[derive(Debug)]
+struct Counter {
+	path: PathBuf,
+	value: u64,
+}
+
+impl Counter {
+	fn open(dir: &Path, name: &str) -> Result<Self> {
+		let path = dir.join(name);
+		let value = read_counter(&path)?;
+		Ok(Counter { path, value })
+	}
+
+	fn set(&mut self, value: u64) -> Result<()> {
+		let tmp = self.path.with_extension("tmp");
+		fs::write(&tmp, value.to_string())?;
+		fs::rename(&tmp, &self.path)?;
+		self.value = value;
+		Ok(())
+	}
+}
+
+// a counter never written is 0, e.g. nothing sent yet
+fn read_counter(path: &Path) -> Result<u64> {
+	match fs::read_to_string(path) {
+		Ok(value) => Ok(value.trim().parse().unwrap_or_default()),
+		Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(0),
+		Err(e) => Err(e.into()),
+	}
+}
+
+struct FileSender {
+	inner: Sender,
+	sent: Counter,
+}
+
+#[async_trait]
+impl QueueSender for FileSender {
+	// counted after the send, a crash in between only makes the depth lower
+	async fn send(&mut self, payload: Vec<u8>) -> Result<()> {
+		self.inner.send(payload).await?;
+		self.sent.set(self.sent.value + 1)
+	}
+}
+
+struct FileReceiver {
+	inner: Receiver,
+	committed: Counter,
+	sent_path: PathBuf,
+}
+
+#[async_trait]
+impl QueueReceiver for FileReceiver {
+	async fn recv_timeout<'a>(&'a mut self, timeout: Duration) -> Result<Option<Delivery<'a>>> {
+		let FileReceiver { inner, committed, sent_path } = self;
+		match inner.recv_timeout(Delay::new(timeout)).await? {
+			Some(guard) => {
+				let ack = FileAck { guard, committed, sent_path };
+				Ok(Some(Delivery::new(ack.guard.to_vec(), Box::new(ack))))
+			},
+			None => Ok(None),
+		}
+	}
+}
+
+// yaque rolls the message back when the guard is dropped
+struct FileAck<'a> {
+	guard: RecvGuard<'a, Vec<u8>>,
+	committed: &'a mut Counter,
+	sent_path: &'a Path,
+}
+
+#[async_trait]
+impl<'a> Acknowledge for FileAck<'a> {
+	// counted before the commit, a crash in between counts the redelivered message twice.
+	// the count never passes the sent one, so the drift of either side is cleared once the
+	// channel is drained
+	async fn commit(self: Box<Self>) -> Result<()> {
+		let FileAck { guard, committed, sent_path } = *self;
+		let sent = read_counter(sent_path)?;
+		committed.set((committed.value + 1).min(sent))?;
+		guard.commit()?;
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::time::Duration;
+
+	use super::FileQueue;
+	use crate::queue::Queue;
+
+	const TIMEOUT: Duration = Duration::from_secs(1);
+
+	#[tokio::test]
+	async fn file_queue_depth_should_count_unread_messages() {
+		let dir = std::env::temp_dir().join(format!("keeper-queue-{}", std::process::id()));
+		let queue = FileQueue::new(&dir);
+		assert_eq!(queue.depth().await.unwrap(), 0);
+
+		let mut sender = queue.sender().await.unwrap();
+		let mut receiver = queue.receiver().await.unwrap();
+		for payload in ["first", "second", "third"] {
+			sender.send(payload.as_bytes().to_vec()).await.unwrap();
+		}
+		assert_eq!(queue.depth().await.unwrap(), 3);
+
+		// dropped without commit
+		{
+			let delivery = receiver.recv_timeout(TIMEOUT).await.unwrap().unwrap();
+			assert_eq!(&*delivery, b"first");
+		}
+		assert_eq!(queue.depth().await.unwrap(), 3);
+		let delivery = receiver.recv_timeout(TIMEOUT).await.unwrap().unwrap();
+		delivery.commit().await.unwrap();
+		assert_eq!(queue.depth().await.unwrap(), 2);
+
+		drop((sender, receiver));
+		let _ = std::fs::remove_dir_all(&dir);
+	}
+}
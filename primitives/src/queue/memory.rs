@@ -0,0 +1,164 @@
+use std::{
+	collections::VecDeque,
+	sync::{Arc, Mutex},
+	time::Duration,
+};
+
+use async_trait::async_trait;
+use tokio::{sync::Notify, time::Instant};
+
+use super::{
+	Acknowledge, Delivery, MqReceiver, MqSender, Queue, QueueReceiver, QueueSender, Result,
+};
+
+/// queue in process memory, every sender and receiver of a `MemoryQueue` share the messages
+#[derive(Clone, Debug, Default)]
+pub struct MemoryQueue {
+	inner: Arc<Inner>,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+	messages: Mutex<VecDeque<Vec<u8>>>,
+	notify: Notify,
+}
+
+impl Inner {
+	fn push_back(&self, payload: Vec<u8>) {
+		self.messages.lock().expect("memory queue poisoned").push_back(payload);
+		self.notify.notify_one();
+	}
+
+	// rolled back messages are delivered first, as yaque does
+	fn push_front(&self, payload: Vec<u8>) {
+		self.messages.lock().expect("memory queue poisoned").push_front(payload);
+		self.notify.notify_one();
+	}
+
+	fn pop_front(&self) -> Option<Vec<u8>> {
+		self.messages.lock().expect("memory queue poisoned").pop_front()
+	}
+}
+
+impl MemoryQueue {
+	/// number of messages waiting for delivery, uncommitted ones return when dropped
+	pub fn len(&self) -> usize {
+		self.inner.messages.lock().expect("memory queue poisoned").len()
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.len() == 0
+	}
+}
+
+#[async_trait]
+impl Queue for MemoryQueue {
+	async fn sender(&self) -> Result<MqSender> {
+		Ok(Box::new(self.clone()))
+	}
+
+	async fn receiver(&self) -> Result<MqReceiver> {
+		Ok(Box::new(self.clone()))
+	}
+
+	async fn depth(&self) -> Result<u64> {
+		Ok(self.len() as u64)
+	}
+}
+
+#[async_trait]
+impl QueueSender for MemoryQueue {
+	async fn send(&mut self, payload: Vec<u8>) -> Result<()> {
+		self.inner.push_back(payload);
+		Ok(())
+	}
+}
+
+#[async_trait]
+impl QueueReceiver for MemoryQueue {
+	async fn recv_timeout<'a>(&'a mut self, timeout: Duration) -> Result<Option<Delivery<'a>>> {
+		let deadline = Instant::now() + timeout;
+		loop {
+			if let Some(payload) = self.inner.pop_front() {
+				let ack = MemoryAck { inner: self.inner.clone(), payload: Some(payload.clone()) };
+				return Ok(Some(Delivery::new(payload, Box::new(ack))))
+			}
+			// a message sent before waiting leaves a permit, so it is not missed
+			if tokio::time::timeout_at(deadline, self.inner.notify.notified()).await.is_err() {
+				return Ok(None)
+			}
+		}
+	}
+}
+
+struct MemoryAck {
+	inner: Arc<Inner>,
+	// `None` once committed
+	payload: Option<Vec<u8>>,
+}
+
+#[async_trait]
+impl Acknowledge for MemoryAck {
+	async fn commit(mut self: Box<Self>) -> Result<()> {
+		self.payload = None;
+		Ok(())
+	}
+}
+
+impl Drop for MemoryAck {
+	fn drop(&mut self) {
+		if let Some(payload) = self.payload.take() {
+			self.inner.push_front(payload);
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::time::Duration;
+
+	use super::MemoryQueue;
+	use crate::queue::Queue;
+
+	const TIMEOUT: Duration = Duration::from_millis(50);
+
+	#[tokio::test]
+	async fn memory_queue_should_redeliver_uncommitted() {
+		let queue = MemoryQueue::default();
+		let mut sender = queue.sender().await.unwrap();
+		let mut receiver = queue.receiver().await.unwrap();
+		assert!(receiver.recv_timeout(TIMEOUT).await.unwrap().is_none());
+
+		sender.send(b"first".to_vec()).await.unwrap();
+		sender.send(b"second".to_vec()).await.unwrap();
+
+		// dropped without commit
+		{
+			let delivery = receiver.recv_timeout(TIMEOUT).await.unwrap().unwrap();
+			assert_eq!(&*delivery, b"first");
+		}
+		let delivery = receiver.recv_timeout(TIMEOUT).await.unwrap().unwrap();
+		assert_eq!(&*delivery, b"first");
+		delivery.commit().await.unwrap();
+
+		let delivery = receiver.recv_timeout(TIMEOUT).await.unwrap().unwrap();
+		assert_eq!(&*delivery, b"second");
+		delivery.commit().await.unwrap();
+		assert!(queue.is_empty());
+	}
+
+	#[tokio::test]
+	async fn memory_queue_should_wake_waiting_receiver() {
+		let queue = MemoryQueue::default();
+		let mut receiver = queue.receiver().await.unwrap();
+		let mut sender = queue.sender().await.unwrap();
+		let send = tokio::spawn(async move {
+			tokio::time::sleep(Duration::from_millis(10)).await;
+			sender.send(b"late".to_vec()).await.unwrap();
+		});
+
+		let delivery = receiver.recv_timeout(Duration::from_secs(5)).await.unwrap().unwrap();
+		assert_eq!(&*delivery, b"late");
+		send.await.unwrap();
+	}
+}
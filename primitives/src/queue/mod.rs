@@ -0,0 +1,184 @@
+use std::{ops::Deref, path::Path, time::Duration};
+
+use async_trait::async_trait;
+
+use super::{Deserialize, Retryable, Serialize};
+
+pub use backpressure::{Backpressure, BackpressureConfig, WaterMarks};
+pub use file::FileQueue;
+pub use memory::MemoryQueue;
+#[cfg(feature = "redis-queue")]
+pub use redis_stream::RedisQueue;
+
+mod backpressure;
+mod file;
+mod memory;
+#[cfg(feature = "redis-queue")]
+mod redis_stream;
+
+pub const QUEUE_LOG_TARGET: &str = "Queue";
+
+pub type MqSender = Box<dyn QueueSender>;
+pub type MqReceiver = Box<dyn QueueReceiver>;
+
+/// where the channels between stages live
+#[derive(Eq, PartialEq, Clone, Debug, Deserialize, Serialize)]
+#[serde(tag = "backend", rename_all = "snake_case")]
+pub enum QueueConfig {
+	// yaque files in the cache dir
+	File,
+	// lost on exit, for tests and ephemeral runs
+	Memory,
+	// redis streams, so stages can run on different hosts
+	Redis {
+		url: String,
+		// name in the consumer group, unique to each keeper process reading the channels and
+		// stable across its restarts. processes sharing it re-read each other's pending
+		// messages, so there is no default
+		consumer: String,
+	},
+}
+
+impl Default for QueueConfig {
+	fn default() -> Self {
+		QueueConfig::File
+	}
+}
+
+impl QueueConfig {
+	/// open the channel stored at `path`, other backends use its file name as channel name
+	pub async fn open(&self, path: &Path) -> Result<Box<dyn Queue>> {
+		match self {
+			QueueConfig::File => Ok(Box::new(FileQueue::new(path))),
+			QueueConfig::Memory => Ok(Box::new(MemoryQueue::default())),
+			#[cfg(feature = "redis-queue")]
+			QueueConfig::Redis { url, consumer } =>
+				Ok(Box::new(RedisQueue::new(url, &channel_name(path)?, consumer)?)),
+			#[cfg(not(feature = "redis-queue"))]
+			QueueConfig::Redis { .. } => Err(Error::Unavailable(
+				"keeper is built without the redis-queue feature".to_owned(),
+			)),
+		}
+	}
+}
+
+#[cfg_attr(not(feature = "redis-queue"), allow(dead_code))]
+fn channel_name(path: &Path) -> Result<String> {
+	path.file_name()
+		.map(|name| name.to_string_lossy().into_owned())
+		.ok_or_else(|| Error::Unavailable(format!("no channel name in {:?}", path)))
+}
+
+/// a channel between two stages, messages are delivered at least once
+#[async_trait]
+pub trait Queue: Send + Sync {
+	async fn sender(&self) -> Result<MqSender>;
+
+	async fn receiver(&self) -> Result<MqReceiver>;
+
+	/// messages sent to the channel and not committed yet
+	async fn depth(&self) -> Result<u64>;
+
+	/// clear the sender lock left by a crashed keeper, the caller must own the sending role
+	fn unlock_sender(&self) -> Result<()> {
+		Ok(())
+	}
+
+	/// clear the receiver lock left by a crashed keeper, the caller must own the receiving role
+	fn unlock_receiver(&self) -> Result<()> {
+		Ok(())
+	}
+}
+
+#[async_trait]
+pub trait QueueSender: Send {
+	async fn send(&mut self, payload: Vec<u8>) -> Result<()>;
+}
+
+#[async_trait]
+pub trait QueueReceiver: Send {
+	/// next message, `None` if nothing arrives within `timeout`
+	async fn recv_timeout<'a>(&'a mut self, timeout: Duration) -> Result<Option<Delivery<'a>>>;
+}
+
+/// removes a delivered message from its queue
+#[async_trait]
+pub trait Acknowledge: Send {
+	async fn commit(self: Box<Self>) -> Result<()>;
+}
+
+/// a received message, delivered again if dropped without `commit`
+pub struct Delivery<'a> {
+	payload: Vec<u8>,
+	ack: Box<dyn Acknowledge + 'a>,
+}
+
+impl<'a> Delivery<'a> {
+	pub fn new(payload: Vec<u8>, ack: Box<dyn Acknowledge + 'a>) -> Self {
+		Delivery { payload, ack }
+	}
+
+	pub async fn commit(self) -> Result<()> {
+		self.ack.commit().await
+	}
+}
+
+impl Deref for Delivery<'_> {
+	type Target = [u8];
+
+	fn deref(&self) -> &[u8] {
+		&self.payload
+	}
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+	#[error("File queue I/O error, err: {0}")]
+	IoError(#[from] std::io::Error),
+	#[cfg(feature = "redis-queue")]
+	#[error("Redis queue error, err: {0}")]
+	RedisError(#[from] redis::RedisError),
+	#[error("Queue unavailable, err: {0}")]
+	Unavailable(String),
+}
+
+// a redis server restarting or out of reach, file and config problems need an operator
+impl Retryable for Error {
+	fn is_retryable(&self) -> bool {
+		match self {
+			#[cfg(feature = "redis-queue")]
+			Error::RedisError(e) =>
+				e.is_io_error() ||
+					e.is_timeout() || e.is_connection_dropped() ||
+					e.is_connection_refusal(),
+			_ => false,
+		}
+	}
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[cfg(test)]
+mod tests {
+	use std::path::Path;
+
+	use super::{channel_name, QueueConfig};
+
+	#[test]
+	fn queue_config_should_default_to_files() {
+		let config: QueueConfig = serde_json::from_str(r#"{"backend":"file"}"#).unwrap();
+		assert_eq!(config, QueueConfig::default());
+
+		let redis = r#"{"backend":"redis","url":"redis://127.0.0.1/","consumer":"host-a-verify"}"#;
+		let config: QueueConfig = serde_json::from_str(redis).unwrap();
+		let expect = QueueConfig::Redis {
+			url: "redis://127.0.0.1/".to_owned(),
+			consumer: "host-a-verify".to_owned(),
+		};
+		assert_eq!(config, expect);
+		// every process names its own consumer
+		let unnamed = r#"{"backend":"redis","url":"redis://127.0.0.1/"}"#;
+		assert!(serde_json::from_str::<QueueConfig>(unnamed).is_err());
+		assert_eq!(channel_name(Path::new("./data/event2ipfs")).unwrap(), "event2ipfs");
+	}
+}
@@ -0,0 +1,180 @@
+use std::time::Duration;
+
+use async_trait::async_trait;
+use redis::{
+	aio::MultiplexedConnection,
+	streams::{StreamId, StreamReadOptions, StreamReadReply},
+	AsyncCommands, Client, RedisResult,
+};
+
+use super::{
+	Acknowledge, Delivery, MqReceiver, MqSender, Queue, QueueReceiver, QueueSender, Result,
+	QUEUE_LOG_TARGET,
+};
+
+// all keepers reading a stream share the group, so each message is handled once
+const GROUP: &str = "zcloak-keeper";
+const KEY_PREFIX: &str = "zcloak-keeper:";
+const PAYLOAD_FIELD: &str = "payload";
+
+/// a redis stream, read through a consumer group
+#[derive(Clone, Debug)]
+pub struct RedisQueue {
+	client: Client,
+	key: String,
+	consumer: String,
+}
+
+impl RedisQueue {
+	pub fn new(url: &str, channel: &str, consumer: &str) -> Result<Self> {
+		Ok(RedisQueue {
+			client: Client::open(url)?,
+			key: format!("{}{}", KEY_PREFIX, channel),
+			consumer: consumer.to_owned(),
+		})
+	}
+}
+
+#[async_trait]
+impl Queue for RedisQueue {
+	async fn sender(&self) -> Result<MqSender> {
+		let conn = self.client.get_multiplexed_tokio_connection().await?;
+		Ok(Box::new(RedisSender { conn, key: self.key.clone() }))
+	}
+
+	async fn receiver(&self) -> Result<MqReceiver> {
+		let mut conn = self.client.get_multiplexed_tokio_connection().await?;
+		let created: RedisResult<()> = conn.xgroup_create_mkstream(&self.key, GROUP, "0").await;
+		// the group exists once any keeper has read the stream
+		if let Err(e) = created {
+			if e.code() != Some("BUSYGROUP") {
+				return Err(e.into())
+			}
+		}
+		log::info!(target: QUEUE_LOG_TARGET, "read {} as {}", self.key, self.consumer);
+		let (key, consumer) = (self.key.clone(), self.consumer.clone());
+		Ok(Box::new(RedisReceiver { conn, key, consumer }))
+	}
+
+	async fn depth(&self) -> Result<u64> {
+		// committed entries are deleted, the rest waits or is pending
+		let mut conn = self.client.get_multiplexed_tokio_connection().await?;
+		let len: u64 = conn.xlen(&self.key).await?;
+		Ok(len)
+	}
+}
+
+struct RedisSender {
+	conn: MultiplexedConnection,
+	key: String,
+}
+
+#[async_trait]
+impl QueueSender for RedisSender {
+	async fn send(&mut self, payload: Vec<u8>) -> Result<()> {
+		let _: String = self.conn.xadd(&self.key, "*", &[(PAYLOAD_FIELD, payload)]).await?;
+		Ok(())
+	}
+}
+
+struct RedisReceiver {
+	conn: MultiplexedConnection,
+	key: String,
+	consumer: String,
+}
+
+impl RedisReceiver {
+	// `0` reads entries delivered to this consumer but never acknowledged, `>` new ones
+	async fn read(&mut self, id: &str, block: Option<Duration>) -> Result<Option<StreamId>> {
+		let mut options = StreamReadOptions::default().group(GROUP, &self.consumer).count(1);
+		if let Some(block) = block {
+			// `BLOCK 0` would wait forever
+			options = options.block((block.as_millis() as usize).max(1));
+		}
+		let reply: Option<StreamReadReply> =
+			self.conn.xread_options(&[&self.key], &[id], &options).await?;
+		Ok(reply
+			.and_then(|reply| reply.keys.into_iter().next())
+			.and_then(|key| key.ids.into_iter().next()))
+	}
+}
+
+#[async_trait]
+impl QueueReceiver for RedisReceiver {
+	async fn recv_timeout<'a>(&'a mut self, timeout: Duration) -> Result<Option<Delivery<'a>>> {
+		// uncommitted messages stay pending and are delivered again, as yaque does
+		let entry = match self.read("0", None).await? {
+			Some(entry) => entry,
+			None => match self.read(">", Some(timeout)).await? {
+				Some(entry) => entry,
+				None => return Ok(None),
+			},
+		};
+		// an entry without payload is left to the stage to dead-letter
+		let payload = entry.get::<Vec<u8>>(PAYLOAD_FIELD).unwrap_or_default();
+		let ack = RedisAck { conn: self.conn.clone(), key: self.key.clone(), id: entry.id };
+		Ok(Some(Delivery::new(payload, Box::new(ack))))
+	}
+}
+
+struct RedisAck {
+	conn: MultiplexedConnection,
+	key: String,
+	id: String,
+}
+
+#[async_trait]
+impl Acknowledge for RedisAck {
+	async fn commit(mut self: Box<Self>) -> Result<()> {
+		// the group is the only reader, so acknowledged entries can be deleted
+		redis::pipe()
+			.atomic()
+			.xack(&self.key, GROUP, &[&self.id])
+			.ignore()
+			.xdel(&self.key, &[&self.id])
+			.ignore()
+			.query_async::<_, ()>(&mut self.conn)
+			.await?;
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::time::Duration;
+
+	use super::RedisQueue;
+	use crate::queue::Queue;
+
+	const TIMEOUT: Duration = Duration::from_millis(100);
+
+	fn redis_url() -> String {
+		std::env::var("REDIS_URL").unwrap_or_else(|_| "redis://127.0.0.1/".to_owned())
+	}
+
+	#[tokio::test]
+	#[ignore = "needs a local redis server, set REDIS_URL to use another one"]
+	async fn redis_queue_should_redeliver_uncommitted() {
+		let channel = format!("test-{}", std::process::id());
+		let queue = RedisQueue::new(&redis_url(), &channel, "tester").unwrap();
+		let mut sender = queue.sender().await.unwrap();
+		let mut receiver = queue.receiver().await.unwrap();
+
+		sender.send(b"first".to_vec()).await.unwrap();
+		sender.send(b"second".to_vec()).await.unwrap();
+		{
+			let delivery = receiver.recv_timeout(TIMEOUT).await.unwrap().unwrap();
+			assert_eq!(&*delivery, b"first");
+		}
+		let delivery = receiver.recv_timeout(TIMEOUT).await.unwrap().unwrap();
+		assert_eq!(&*delivery, b"first");
+		delivery.commit().await.unwrap();
+
+		// another keeper process of the same consumer resumes after the commit
+		let mut restarted = queue.receiver().await.unwrap();
+		let delivery = restarted.recv_timeout(TIMEOUT).await.unwrap().unwrap();
+		assert_eq!(&*delivery, b"second");
+		delivery.commit().await.unwrap();
+		assert!(restarted.recv_timeout(TIMEOUT).await.unwrap().is_none());
+	}
+}
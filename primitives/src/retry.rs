@@ -0,0 +1,192 @@
+use std::{fmt::Debug, future::Future, time::Duration};
+
+use rand::Rng;
+
+use super::{Deserialize, Serialize};
+
+/// errors worth another attempt, e.g. timeouts and dropped connections
+pub trait Retryable {
+	fn is_retryable(&self) -> bool;
+}
+
+fn default_max_attempts() -> u32 {
+	5
+}
+
+fn default_base_delay_ms() -> u64 {
+	500
+}
+
+fn default_max_delay_ms() -> u64 {
+	30_000
+}
+
+fn default_jitter() -> bool {
+	true
+}
+
+/// how network calls of a component are retried
+#[derive(Eq, PartialEq, Clone, Debug, Deserialize, Serialize)]
+pub struct RetryPolicy {
+	// attempts in total, the first one included
+	#[serde(default = "default_max_attempts")]
+	pub max_attempts: u32,
+	// delay before the first retry, doubled on each of the next ones
+	#[serde(default = "default_base_delay_ms")]
+	pub base_delay_ms: u64,
+	#[serde(default = "default_max_delay_ms")]
+	pub max_delay_ms: u64,
+	// wait a random time between half and the whole delay, keepers do not retry in lockstep
+	#[serde(default = "default_jitter")]
+	pub jitter: bool,
+}
+
+impl Default for RetryPolicy {
+	fn default() -> Self {
+		RetryPolicy {
+			max_attempts: default_max_attempts(),
+			base_delay_ms: default_base_delay_ms(),
+			max_delay_ms: default_max_delay_ms(),
+			jitter: default_jitter(),
+		}
+	}
+}
+
+impl RetryPolicy {
+	/// delay after the `failures`th consecutive failure, counting from 1
+	pub fn delay(&self, failures: u32) -> Duration {
+		let exponent = failures.saturating_sub(1).min(63);
+		let delay = self
+			.base_delay_ms
+			.saturating_mul(1u64.checked_shl(exponent).unwrap_or(u64::MAX))
+			.min(self.max_delay_ms);
+		let delay = if self.jitter && delay > 1 {
+			rand::thread_rng().gen_range(delay / 2..=delay)
+		} else {
+			delay
+		};
+		Duration::from_millis(delay)
+	}
+
+	/// run `f` until it succeeds, fails with an error not retryable or runs out of attempts
+	pub async fn retry<T, E, F, Fut>(&self, target: &str, what: &str, f: F) -> Result<T, E>
+	where
+		E: Retryable + Debug,
+		F: FnMut() -> Fut,
+		Fut: Future<Output = Result<T, E>>,
+	{
+		self.retry_if(target, what, E::is_retryable, f).await
+	}
+
+	/// as `retry`, with errors to retry chosen by `retryable`
+	pub async fn retry_if<T, E, F, Fut, P>(
+		&self,
+		target: &str,
+		what: &str,
+		retryable: P,
+		mut f: F,
+	) -> Result<T, E>
+	where
+		E: Debug,
+		F: FnMut() -> Fut,
+		Fut: Future<Output = Result<T, E>>,
+		P: Fn(&E) -> bool,
+	{
+		let mut failures = 0;
+		loop {
+			match f().await {
+				Ok(t) => return Ok(t),
+				Err(e) => {
+					failures += 1;
+					if failures >= self.max_attempts || !retryable(&e) {
+						return Err(e)
+					}
+					let delay = self.delay(failures);
+					log::warn!(
+						target: target,
+						"{} fails, retry {}/{} in {:?}, err: {:?}",
+						what,
+						failures,
+						self.max_attempts - 1,
+						delay,
+						e
+					);
+					tokio::time::sleep(delay).await;
+				},
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::{cell::Cell, time::Duration};
+
+	use super::{RetryPolicy, Retryable};
+
+	#[derive(Debug, PartialEq)]
+	struct Flaky(bool);
+
+	impl Retryable for Flaky {
+		fn is_retryable(&self) -> bool {
+			self.0
+		}
+	}
+
+	fn policy(max_attempts: u32) -> RetryPolicy {
+		RetryPolicy { max_attempts, base_delay_ms: 1, max_delay_ms: 4, jitter: false }
+	}
+
+	#[test]
+	fn delay_should_grow_up_to_max() {
+		let policy = RetryPolicy { base_delay_ms: 100, max_delay_ms: 1000, ..policy(5) };
+		assert_eq!(policy.delay(1), Duration::from_millis(100));
+		assert_eq!(policy.delay(3), Duration::from_millis(400));
+		assert_eq!(policy.delay(5), Duration::from_millis(1000));
+		assert_eq!(policy.delay(u32::MAX), Duration::from_millis(1000));
+
+		let jittered = RetryPolicy { jitter: true, ..policy };
+		for _ in 0..20 {
+			let delay = jittered.delay(2);
+			assert!(delay >= Duration::from_millis(100) && delay <= Duration::from_millis(200));
+		}
+	}
+
+	#[tokio::test]
+	async fn retry_should_stop_at_max_attempts() {
+		let calls = &Cell::new(0);
+		let res: Result<(), Flaky> = policy(3)
+			.retry("test", "flaky call", move || async move {
+				calls.set(calls.get() + 1);
+				Err(Flaky(true))
+			})
+			.await;
+		assert_eq!(res, Err(Flaky(true)));
+		assert_eq!(calls.get(), 3);
+	}
+
+	#[tokio::test]
+	async fn retry_should_not_repeat_permanent_errors() {
+		let calls = &Cell::new(0);
+		let res = policy(3)
+			.retry("test", "flaky call", move || async move {
+				calls.set(calls.get() + 1);
+				match calls.get() {
+					1 => Err(Flaky(true)),
+					_ => Ok(calls.get()),
+				}
+			})
+			.await;
+		assert_eq!(res, Ok(2));
+
+		calls.set(0);
+		let res: Result<(), Flaky> = policy(3)
+			.retry("test", "flaky call", move || async move {
+				calls.set(calls.get() + 1);
+				Err(Flaky(false))
+			})
+			.await;
+		assert_eq!(res, Err(Flaky(false)));
+		assert_eq!(calls.get(), 1);
+	}
+}
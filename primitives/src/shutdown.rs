@@ -0,0 +1,76 @@
+use std::sync::Arc;
+
+use tokio::sync::watch;
+
+/// asks every task of the keeper to stop, they finish the work at hand first
+#[derive(Clone, Debug)]
+pub struct ShutdownTrigger {
+	sender: Arc<watch::Sender<bool>>,
+	receiver: watch::Receiver<bool>,
+}
+
+impl ShutdownTrigger {
+	pub fn new() -> Self {
+		let (sender, receiver) = watch::channel(false);
+		ShutdownTrigger { sender: Arc::new(sender), receiver }
+	}
+
+	pub fn request(&self) {
+		// a receiver is kept by the trigger, sending never fails
+		let _ = self.sender.send(true);
+	}
+
+	pub fn is_requested(&self) -> bool {
+		*self.receiver.borrow()
+	}
+
+	pub fn subscribe(&self) -> Shutdown {
+		Shutdown(self.receiver.clone())
+	}
+}
+
+impl Default for ShutdownTrigger {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+/// tells a task the keeper is stopping
+#[derive(Clone, Debug)]
+pub struct Shutdown(watch::Receiver<bool>);
+
+impl Shutdown {
+	pub fn is_requested(&self) -> bool {
+		*self.0.borrow()
+	}
+
+	/// resolves once a shutdown is requested
+	pub async fn requested(&mut self) {
+		while !self.is_requested() {
+			// all triggers are gone, nobody is left to ask for it
+			if self.0.changed().await.is_err() {
+				return std::future::pending().await
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::time::Duration;
+
+	use super::ShutdownTrigger;
+
+	#[tokio::test]
+	async fn shutdown_should_reach_every_subscriber() {
+		let trigger = ShutdownTrigger::new();
+		let (mut first, second) = (trigger.subscribe(), trigger.subscribe());
+		assert!(!second.is_requested());
+		let timeout = tokio::time::timeout(Duration::from_millis(10), first.requested()).await;
+		assert!(timeout.is_err());
+
+		trigger.clone().request();
+		first.requested().await;
+		assert!(second.is_requested() && trigger.is_requested());
+	}
+}
@@ -0,0 +1,77 @@
+use std::{fmt, path::PathBuf, str::FromStr};
+
+use super::{ChannelFiles, Deserialize, Serialize};
+
+/// pipeline stage a message is consumed by
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Stage {
+	Ipfs,
+	Kilt,
+	Submit,
+}
+
+impl Stage {
+	pub const ALL: [Stage; 3] = [Stage::Ipfs, Stage::Kilt, Stage::Submit];
+
+	pub(crate) fn dead_letter_tree(&self) -> &'static str {
+		match self {
+			Stage::Ipfs => "dead_letters_ipfs",
+			Stage::Kilt => "dead_letters_kilt",
+			Stage::Submit => "dead_letters_submit",
+		}
+	}
+
+	pub(crate) fn processed_tree(&self) -> &'static str {
+		match self {
+			Stage::Ipfs => "processed_ipfs",
+			Stage::Kilt => "processed_kilt",
+			Stage::Submit => "processed_submit",
+		}
+	}
+
+	/// channel the stage receives from, where dead letters are requeued
+	pub fn input_channel<'a>(&self, files: &'a ChannelFiles) -> &'a PathBuf {
+		match self {
+			Stage::Ipfs => &files.event_to_ipfs,
+			Stage::Kilt => &files.verify_to_attest,
+			Stage::Submit => &files.attest_to_submit,
+		}
+	}
+}
+
+impl fmt::Display for Stage {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Stage::Ipfs => write!(f, "ipfs"),
+			Stage::Kilt => write!(f, "kilt"),
+			Stage::Submit => write!(f, "submit"),
+		}
+	}
+}
+
+impl FromStr for Stage {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s {
+			"ipfs" => Ok(Stage::Ipfs),
+			"kilt" => Ok(Stage::Kilt),
+			"submit" => Ok(Stage::Submit),
+			_ => Err(format!("unknown stage {}, expect one of ipfs, kilt, submit", s)),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::Stage;
+
+	#[test]
+	fn stage_should_parse_from_display() {
+		for stage in Stage::ALL {
+			assert_eq!(stage.to_string().parse::<Stage>(), Ok(stage));
+		}
+		assert!("scan".parse::<Stage>().is_err());
+	}
+}
@@ -12,3 +12,8 @@ pub trait JsonParse {
 	where
 		Self: Sized;
 }
+
+/// identifies a request across pipeline stages and channel redeliveries
+pub trait Idempotent {
+	fn idempotency_key(&self) -> Vec<u8>;
+}
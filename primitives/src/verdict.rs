@@ -0,0 +1,110 @@
+use super::{Deserialize, Serialize};
+
+pub const VERDICT_LOG_TARGET: &str = "Verdict";
+
+/// the reason a proof request passes or fails, carried from the ipfs stage
+/// through the kilt stage to the submitter
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Hash, Serialize, Deserialize)]
+pub enum Verdict {
+	/// no stage has decided yet, never submitted
+	Unverified,
+	/// proof is verified and the credential is attested
+	Valid,
+	/// StarksVM rejects the proof
+	ProofInvalid,
+	/// proof content can not be decoded into `StarkProof`
+	ProofMalformed,
+	/// ipfs answers the cid with an error, e.g. the content is not found
+	ProofUnavailable,
+	/// no attestation on KILT for the root hash
+	AttestationMissing,
+	/// attestation can not be read or decoded from KILT
+	AttestationUnavailable,
+	/// attestation has been revoked by its attester
+	AttestationRevoked,
+	/// ctype claimed in the event does not match the attestation
+	CTypeMismatch,
+	/// attester claimed in the event does not match the attestation
+	AttesterMismatch,
+	/// attester or delegation hierarchy is not trusted by the keeper
+	AttesterUntrusted,
+	/// attester DID is deleted or has never been created
+	AttesterDidInactive,
+}
+
+// a request no stage has decided on must not end up on-chain
+impl Default for Verdict {
+	fn default() -> Self {
+		Verdict::Unverified
+	}
+}
+
+impl Verdict {
+	pub fn is_valid(&self) -> bool {
+		*self == Verdict::Valid
+	}
+}
+
+/// what the submitter does with a verdict
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum Decision {
+	SubmitPassed,
+	SubmitFailed,
+	Withhold,
+}
+
+#[derive(Eq, PartialEq, Clone, Debug, Deserialize, Serialize)]
+pub struct VerdictPolicy {
+	// failing verdicts submitted on-chain as `isPassed = false`,
+	// the rest are only recorded locally
+	pub submit_as_failure: Vec<Verdict>,
+}
+
+impl Default for VerdictPolicy {
+	fn default() -> Self {
+		VerdictPolicy { submit_as_failure: vec![Verdict::ProofInvalid, Verdict::ProofMalformed] }
+	}
+}
+
+impl VerdictPolicy {
+	pub fn decide(&self, verdict: Verdict) -> Decision {
+		if verdict.is_valid() {
+			Decision::SubmitPassed
+		} else if self.submit_as_failure.contains(&verdict) {
+			Decision::SubmitFailed
+		} else {
+			Decision::Withhold
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{Decision, Verdict, VerdictPolicy};
+
+	#[test]
+	fn default_policy_should_withhold_credential_failures() {
+		let policy = VerdictPolicy::default();
+		assert_eq!(policy.decide(Verdict::Valid), Decision::SubmitPassed);
+		assert_eq!(policy.decide(Verdict::ProofInvalid), Decision::SubmitFailed);
+		assert_eq!(policy.decide(Verdict::ProofMalformed), Decision::SubmitFailed);
+		assert_eq!(policy.decide(Verdict::ProofUnavailable), Decision::Withhold);
+		assert_eq!(policy.decide(Verdict::AttestationMissing), Decision::Withhold);
+		assert_eq!(policy.decide(Verdict::AttestationRevoked), Decision::Withhold);
+		assert_eq!(policy.decide(Verdict::AttestationUnavailable), Decision::Withhold);
+		assert_eq!(policy.decide(Verdict::CTypeMismatch), Decision::Withhold);
+		assert_eq!(policy.decide(Verdict::AttesterMismatch), Decision::Withhold);
+		assert_eq!(policy.decide(Verdict::AttesterUntrusted), Decision::Withhold);
+		assert_eq!(policy.decide(Verdict::AttesterDidInactive), Decision::Withhold);
+		assert_eq!(policy.decide(Verdict::Unverified), Decision::Withhold);
+		assert_eq!(policy.decide(Verdict::default()), Decision::Withhold);
+	}
+
+	#[test]
+	fn verdict_policy_parse_should_work() {
+		let json_str = r#"{"submit_as_failure":["ProofInvalid","AttestationRevoked"]}"#;
+		let policy: VerdictPolicy = serde_json::from_str(json_str).unwrap();
+		assert_eq!(policy.decide(Verdict::AttestationRevoked), Decision::SubmitFailed);
+		assert_eq!(policy.decide(Verdict::ProofMalformed), Decision::Withhold);
+	}
+}
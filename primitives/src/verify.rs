@@ -1,5 +1,8 @@
+use codec::Encode;
 use starksVM as stark;
 
+use crate::Verdict;
+
 pub const VERIFY_LOG_TARGET: &str = "StarkVerify";
 
 pub fn verify_proof(
@@ -19,6 +22,22 @@ pub fn verify_proof(
 	}
 }
 
+/// key of the verification cache: everything `verify_proof` depends on,
+/// with the raw proof body reduced to its hash
+pub fn verify_cache_key(
+	program_hash: &[u8; 32],
+	body: &[u8],
+	public_inputs: &[u128],
+	outputs: &[u128],
+) -> [u8; 32] {
+	let mut preimage = program_hash.to_vec();
+	preimage.extend_from_slice(&sp_core::blake2_256(body));
+	// SCALE encoding carries the length prefix, so inputs and outputs can not be shifted
+	preimage.extend(public_inputs.encode());
+	preimage.extend(outputs.encode());
+	sp_core::blake2_256(&preimage)
+}
+
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
 	#[error("Hex Decode Error: err{0}")]
@@ -28,6 +47,17 @@ pub enum Error {
 	#[error("StarksVM Verify Error: err{0}")]
 	VerifyError(String),
 }
+impl Error {
+	// proof bytes which can not be parsed are malformed,
+	// everything the verifier itself rejects is invalid
+	pub fn verdict(&self) -> Verdict {
+		match self {
+			Error::HexError(_) | Error::StarkProofDeserializeError(_) => Verdict::ProofMalformed,
+			Error::VerifyError(_) => Verdict::ProofInvalid,
+		}
+	}
+}
+
 pub type Result<T> = std::result::Result<T, Error>;
 
 #[cfg(test)]
@@ -35,6 +65,17 @@ mod tests {
 
 	use crate::ipfs::IpfsClient;
 
+	#[test]
+	fn verify_cache_key_should_distinguish_inputs() {
+		let program_hash = [1u8; 32];
+		let key = super::verify_cache_key(&program_hash, b"proof", &[1, 2], &[3]);
+		assert_eq!(key, super::verify_cache_key(&program_hash, b"proof", &[1, 2], &[3]));
+		assert_ne!(key, super::verify_cache_key(&[2u8; 32], b"proof", &[1, 2], &[3]));
+		assert_ne!(key, super::verify_cache_key(&program_hash, b"proof2", &[1, 2], &[3]));
+		assert_ne!(key, super::verify_cache_key(&program_hash, b"proof", &[1], &[2, 3]));
+		assert_ne!(key, super::verify_cache_key(&program_hash, b"proof", &[1, 2], &[4]));
+	}
+
 	#[tokio::test]
 	async fn verify_should_work() {
 		let program_hash = [